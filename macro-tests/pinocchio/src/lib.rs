@@ -0,0 +1,32 @@
+//! A program calling the raw syscalls, as pinocchio programs do. It can't
+//! share a binary with the loader, whose exports carry the same symbols.
+
+solana_program_stubs::declare_pinocchio_app_stubs!(process_entrypoint);
+
+/// The program's own declarations of the syscalls it makes, as
+/// `solana_define_syscall::definitions` has them, but unwinding.
+mod syscalls {
+    extern "C-unwind" {
+        pub fn sol_log_(message: *const u8, len: u64);
+        pub fn sol_panic_(file: *const u8, len: u64, line: u64, column: u64) -> !;
+    }
+}
+
+/// Instructions of the app, in the first byte of its input.
+pub const LOG_TWICE: u8 = 0;
+pub const PANIC: u8 = 1;
+pub const SOL_PANIC: u8 = 2;
+
+/// What the program's `entrypoint` would do with `input`.
+unsafe fn process_entrypoint(input: *mut u8) -> u64 {
+    match unsafe { *input } {
+        LOG_TWICE => unsafe {
+            syscalls::sol_log_("first".as_ptr(), 5);
+            syscalls::sol_log_("second".as_ptr(), 6);
+        },
+        PANIC => panic!("the program panicked"),
+        SOL_PANIC => unsafe { syscalls::sol_panic_("lib.rs".as_ptr(), 6, 1, 1) },
+        _ => return SYSCALL_FAILURE,
+    }
+    0
+}
@@ -0,0 +1,248 @@
+//! Upstream pinocchio compiles its syscalls out of native builds, so the
+//! exported symbols are reached by code that declares the raw syscalls itself,
+//! as `solana_define_syscall::definitions` does on every target. This test
+//! declares them the same way and checks that the calls land in the table the
+//! loader installed.
+
+use solana_program_stubs_pinocchio_tests as program;
+use std::{cell::Cell, sync::Mutex};
+
+extern "C-unwind" {
+    fn sol_log_(message: *const u8, len: u64);
+    fn sol_get_clock_sysvar(addr: *mut u8) -> u64;
+    fn sol_invoke_signed_c(
+        instruction_addr: *const u8,
+        account_infos_addr: *const u8,
+        account_infos_len: u64,
+        signers_seeds_addr: *const u8,
+        signers_seeds_len: u64,
+    ) -> u64;
+}
+
+/// Defines a table entry for each syscall the test doesn't make.
+macro_rules! unused_syscalls {
+    ($($name:ident($($arg:ty),*) $(-> $ret:ty)?;)*) => {
+        mod unused {
+            use super::program::*;
+            $(pub extern "C" fn $name($(_: $arg),*) $(-> $ret)? {
+                unimplemented!(stringify!($name))
+            })*
+        }
+
+        fn stubs_api() -> program::SyscallStubsApi {
+            program::SyscallStubsApi {
+                header: program::SyscallStubsApiHeader::current(program::SYSCALL_STUBS_CAP_PANIC),
+                sol_log_: log,
+                sol_invoke_signed_c: invoke_signed_c,
+                sol_panic_: panic,
+                program_aborted: aborted,
+                layout_fingerprint: program::SYSCALL_STUBS_ABI_FINGERPRINT,
+                $($name: unused::$name,)*
+            }
+        }
+    };
+}
+
+unused_syscalls! {
+    sol_log_compute_units_();
+    sol_remaining_compute_units() -> u64;
+    sol_get_clock_sysvar(*mut u8) -> u64;
+    sol_get_epoch_schedule_sysvar(*mut u8) -> u64;
+    sol_get_fees_sysvar(*mut u8) -> u64;
+    sol_get_rent_sysvar(*mut u8) -> u64;
+    sol_get_last_restart_slot(*mut u8) -> u64;
+    sol_get_sysvar(*const u8, *mut u8, u64, u64) -> u64;
+    sol_memcpy_(*mut u8, *const u8, u64);
+    sol_memmove_(*mut u8, *const u8, u64);
+    sol_memcmp_(*const u8, *const u8, u64, *mut i32);
+    sol_memset_(*mut u8, u8, u64);
+    sol_get_return_data(*mut u8, u64, *mut CPubkey) -> u64;
+    sol_set_return_data(*const u8, u64);
+    sol_log_data(*const u8, u64);
+    sol_get_processed_sibling_instruction(
+        u64,
+        *mut CProcessedSiblingInstruction,
+        *mut CPubkey,
+        *mut u8,
+        *mut CAccountMeta
+    ) -> u64;
+    sol_get_stack_height() -> u64;
+    sol_get_epoch_rewards_sysvar(*mut u8) -> u64;
+    sol_get_epoch_stake(*const u8) -> u64;
+    sol_sha256(*const u8, u64, *mut u8) -> u64;
+    sol_keccak256(*const u8, u64, *mut u8) -> u64;
+    sol_blake3(*const u8, u64, *mut u8) -> u64;
+    sol_create_program_address(*const u8, u64, *const u8, *mut u8) -> u64;
+    sol_try_find_program_address(*const u8, u64, *const u8, *mut u8, *mut u8) -> u64;
+    sol_secp256k1_recover(*const u8, u64, *const u8, *mut u8) -> u64;
+    sol_curve_validate_point(u64, *const u8, *mut u8) -> u64;
+    sol_curve_group_op(u64, u64, *const u8, *const u8, *mut u8) -> u64;
+    sol_curve_multiscalar_mul(u64, *const u8, *const u8, u64, *mut u8) -> u64;
+    sol_alt_bn128_group_op(u64, *const u8, u64, *mut u8) -> u64;
+    sol_alt_bn128_compression(u64, *const u8, u64, *mut u8) -> u64;
+    sol_poseidon(u64, u64, *const u8, u64, *mut u8) -> u64;
+    sol_big_mod_exp(*const u8, *mut u8) -> u64;
+    sol_log_64_(u64, u64, u64, u64, u64);
+    sol_log_pubkey(*const u8);
+}
+
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static PANICS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+std::thread_local! {
+    /// Whether the loader aborted the program running on this thread.
+    static ABORTED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn text(bytes: *const u8, len: u64) -> String {
+    let bytes = unsafe { std::slice::from_raw_parts(bytes, len as usize) };
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+extern "C" fn log(message: *const u8, len: u64) {
+    LOGS.lock().unwrap().push(text(message, len));
+}
+
+extern "C" fn panic(
+    file: *const u8,
+    file_len: u64,
+    _line: u64,
+    _column: u64,
+    message: *const u8,
+    message_len: u64,
+) {
+    let report = format!("{}: {}", text(file, file_len), text(message, message_len));
+    PANICS.lock().unwrap().push(report);
+}
+
+extern "C" fn aborted() -> u64 {
+    ABORTED.get() as u64
+}
+
+/// An account serialized with 20 KiB of data, which the program grew by 2 bytes.
+const SERIALIZED_DATA_LEN: u64 = 20 * 1024;
+const RESIZE_DELTA: i32 = 2;
+
+/// Checks the account the program passed and grows its data as far as the
+/// runtime allows.
+extern "C" fn invoke_signed_c(
+    _instruction_addr: *const u8,
+    account_infos_addr: *const u8,
+    account_infos_len: u64,
+    _signers_seeds_addr: *const u8,
+    _signers_seeds_len: u64,
+) -> u64 {
+    assert_eq!(account_infos_len, 1);
+    let account_info = unsafe { &mut *(account_infos_addr as *mut program::CAccountInfo) };
+    assert_eq!(
+        account_info.data_len,
+        SERIALIZED_DATA_LEN + RESIZE_DELTA as u64
+    );
+    assert_eq!(account_info.original_data_len, SERIALIZED_DATA_LEN);
+    assert_eq!(
+        account_info.data_capacity,
+        SERIALIZED_DATA_LEN + program::MAX_PERMITTED_DATA_INCREASE as u64
+    );
+    unsafe {
+        std::ptr::write_bytes(
+            account_info.data as *mut u8,
+            7,
+            account_info.data_capacity as usize,
+        )
+    };
+    account_info.data_len = account_info.data_capacity;
+    0
+}
+
+/// The account pinocchio's `AccountInfo` points to: the runtime's serialized
+/// account, whose data follows it, with pinocchio's `resize_delta` in the
+/// padding the runtime leaves zeroed.
+#[repr(C)]
+struct PinocchioAccount {
+    borrow_state: u8,
+    is_signer: u8,
+    is_writable: u8,
+    executable: u8,
+    resize_delta: i32,
+    key: [u8; 32],
+    owner: [u8; 32],
+    lamports: u64,
+    data_len: u64,
+}
+
+#[test]
+fn native_calls_reach_the_exported_symbols() {
+    // Syscalls made before `set_stubs` fail rather than abort the program.
+    assert_eq!(
+        unsafe { sol_get_clock_sysvar([0u8; 40].as_mut_ptr()) },
+        program::SYSCALL_FAILURE
+    );
+    unsafe { sol_log_("dropped".as_ptr(), 7) };
+
+    assert_eq!(program::set_stubs(&stubs_api()), program::SET_STUBS_SUCCESS);
+    unsafe { sol_log_("hello".as_ptr(), 5) };
+    assert_eq!(*LOGS.lock().unwrap(), ["hello"]);
+
+    let capacity = SERIALIZED_DATA_LEN as usize + program::MAX_PERMITTED_DATA_INCREASE;
+    let mut input = vec![0u64; (std::mem::size_of::<PinocchioAccount>() + capacity) / 8];
+    let account = input.as_mut_ptr() as *mut PinocchioAccount;
+    unsafe {
+        (*account).borrow_state = u8::MAX;
+        (*account).is_writable = 1;
+        (*account).resize_delta = RESIZE_DELTA;
+        (*account).data_len = SERIALIZED_DATA_LEN + RESIZE_DELTA as u64;
+    }
+    let mut account_info = unsafe {
+        program::SolAccountInfo {
+            key: (*account).key.as_ptr() as *const program::CPubkey,
+            lamports: &(*account).lamports,
+            data_len: (*account).data_len,
+            data: account.add(1) as *const u8,
+            owner: (*account).owner.as_ptr() as *const program::CPubkey,
+            rent_epoch: 0,
+            is_signer: false,
+            is_writable: true,
+            executable: false,
+        }
+    };
+    let result = unsafe {
+        sol_invoke_signed_c(
+            std::ptr::null(),
+            &mut account_info as *mut program::SolAccountInfo as *const u8,
+            1,
+            std::ptr::null(),
+            0,
+        )
+    };
+    assert_eq!(result, 0);
+    assert_eq!(account_info.data_len, capacity as u64);
+    assert_eq!(unsafe { (*account).data_len }, capacity as u64);
+    // pinocchio's next `resize` sees the whole increase as used.
+    assert_eq!(
+        unsafe { (*account).resize_delta },
+        program::MAX_PERMITTED_DATA_INCREASE as i32
+    );
+
+    aborted_programs_are_stopped();
+}
+
+/// Runs the app through `stubs_entrypoint`, once the table is installed.
+fn aborted_programs_are_stopped() {
+    let run = |instruction: u8| unsafe { program::stubs_entrypoint(&mut [instruction] as *mut u8) };
+
+    // Panics are reported to the loader and stop the program.
+    assert_eq!(run(program::PANIC), program::ENTRYPOINT_ABORTED);
+    assert_eq!(run(program::SOL_PANIC), program::ENTRYPOINT_ABORTED);
+    let panics = PANICS.lock().unwrap().clone();
+    assert!(panics[0].ends_with(": the program panicked"));
+    assert_eq!(panics[1], "lib.rs: ");
+
+    // So does an abort the loader reports during a syscall.
+    ABORTED.set(true);
+    assert_eq!(run(program::LOG_TWICE), program::ENTRYPOINT_ABORTED);
+    ABORTED.set(false);
+    assert_eq!(run(program::LOG_TWICE), 0);
+    let logs = LOGS.lock().unwrap();
+    let count = |log: &str| logs.iter().filter(|l| *l == log).count();
+    assert_eq!((count("first"), count("second")), (2, 1));
+}
@@ -0,0 +1,100 @@
+//! A program and its loader expanded side by side, as in a test binary that
+//! links both, so that the macros are built, linted and tested in this
+//! workspace the way downstream crates use them.
+
+pub mod app {
+    use solana_program::{
+        account_info::AccountInfo,
+        entrypoint::{deserialize, ProgramResult},
+        instruction::{AccountMeta, Instruction},
+        program_error::ProgramError,
+        program_stubs::{set_syscall_stubs, SyscallStubs},
+        pubkey::Pubkey,
+    };
+
+    solana_program_stubs::declare_sol_app_stubs!(process_instruction);
+
+    /// Instructions of the program the tests run, as the first byte of its
+    /// instruction data.
+    pub const SUCCEED: u8 = 0;
+    pub const PANIC: u8 = 1;
+    pub const GET_CLOCK: u8 = 2;
+    pub const SET_OVERSIZED_RETURN_DATA: u8 = 3;
+
+    fn process_instruction(
+        _program_id: &Pubkey,
+        _accounts: &[AccountInfo],
+        instruction_data: &[u8],
+    ) -> ProgramResult {
+        match instruction_data.first() {
+            Some(&SUCCEED) => Ok(()),
+            Some(&PANIC) => panic!("the program panicked"),
+            Some(&GET_CLOCK) => {
+                <solana_program::clock::Clock as solana_program::sysvar::Sysvar>::get()?;
+                Ok(())
+            }
+            Some(&SET_OVERSIZED_RETURN_DATA) => {
+                solana_program::program::set_return_data(&[0; MAX_RETURN_DATA + 1]);
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+pub mod loader {
+    use solana_program::{
+        account_info::AccountInfo,
+        entrypoint::ProgramResult,
+        instruction::{AccountMeta, Instruction},
+        program_error::ProgramError,
+        program_stubs::SyscallStubs,
+        pubkey::Pubkey,
+    };
+    use std::sync::{Arc, RwLock};
+
+    solana_program_stubs::declare_sol_loader_stubs!();
+}
+
+use loader::LoaderSyscallStubsApi as _;
+
+/// Hands the loader's syscall table to the program, as a loader does once it
+/// has loaded it.
+pub fn set_loader_stubs() {
+    let stubs_api = loader::SyscallStubsApi::new();
+    assert_eq!(app::set_stubs(&stubs_api), app::SET_STUBS_SUCCESS);
+}
+
+/// Makes syscalls on the calling thread dispatch to `stubs` until the guard
+/// is dropped, so that tests running in parallel don't share stubs.
+pub fn enter_stubs(stubs: Box<dyn loader::LoaderSyscallStubs>) -> loader::StubContextGuard {
+    let context = loader::register_stub_context(stubs);
+    loader::enter_stub_context(context).unwrap()
+}
+
+/// Serializes an input without accounts for `instruction`, the way the
+/// runtime lays it out for the entrypoint.
+pub fn serialize_input(
+    program_id: &solana_program::pubkey::Pubkey,
+    instruction: &[u8],
+) -> Vec<u64> {
+    let mut input = vec![0u8; 8];
+    input.extend((instruction.len() as u64).to_le_bytes());
+    input.extend(instruction);
+    input.extend(program_id.as_ref());
+    // Backed by `u64`s so that the entrypoint can read them aligned.
+    input
+        .chunks(8)
+        .map(|chunk| {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word)
+        })
+        .collect()
+}
+
+/// Runs the program's `stubs_entrypoint` with `instruction`, as a loader does.
+pub fn run_program(instruction: &[u8]) -> Result<u64, loader::ProgramAbort> {
+    let mut input = serialize_input(&solana_program::pubkey::Pubkey::new_unique(), instruction);
+    unsafe { loader::invoke_entrypoint(app::stubs_entrypoint, input.as_mut_ptr() as *mut u8) }
+}
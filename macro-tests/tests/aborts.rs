@@ -0,0 +1,42 @@
+//! Runs on its own: it replaces the loader's global stubs and counts the
+//! process's threads.
+
+use solana_program_stubs_macro_tests::{app, loader, run_program, set_loader_stubs};
+
+/// Threads of this process, where the OS tells.
+fn thread_count() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let threads = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))?;
+    threads.trim().parse().ok()
+}
+
+#[test]
+fn aborted_programs_release_their_thread_and_locks() {
+    set_loader_stubs();
+    *loader::SYSCALL_STUBS.write().unwrap() = Box::new(
+        loader::MeteredSyscallStubs::new(Box::new(loader::NativeSyscallStubs::default()))
+            .with_budget(0),
+    );
+    assert_eq!(run_program(&[app::SUCCEED]), Ok(0));
+    let threads = thread_count();
+    for _ in 0..200 {
+        match run_program(&[app::PANIC]) {
+            Err(loader::ProgramAbort::Panic(panic)) => {
+                assert_eq!(panic.message, "the program panicked");
+                assert!(panic.file.ends_with("lib.rs"));
+            }
+            other => panic!("unexpected outcome {other:?}"),
+        }
+        assert_eq!(
+            run_program(&[app::GET_CLOCK]),
+            Err(loader::ProgramAbort::ComputeBudgetExceeded { budget: 0 })
+        );
+        // The program was stopped inside syscalls made under both sides'
+        // global stubs locks; neither may stay held.
+        drop(loader::SYSCALL_STUBS.try_write().unwrap());
+        set_loader_stubs();
+    }
+    assert_eq!(thread_count(), threads);
+}
@@ -0,0 +1,233 @@
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use solana_program_stubs_macro_tests::app;
+use solana_program_stubs_macro_tests::loader::{
+    self, CAbiError, CAbiRef, CAccountInfo, CAccountMeta, CAccountMetaExt, CInstruction,
+    CInstructionBuf, CPubkey, MAX_CPI_INSTRUCTION_ACCOUNTS, MAX_CPI_INSTRUCTION_DATA_LEN,
+    MAX_PERMITTED_DATA_LENGTH,
+};
+
+fn instruction() -> Instruction {
+    Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[1, 2, 3],
+        vec![
+            AccountMeta::new(Pubkey::new_unique(), true),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        ],
+    )
+}
+
+#[test]
+fn instructions_round_trip() {
+    let instruction = instruction();
+    let cinstruction = CInstructionBuf::try_from(&instruction).unwrap();
+    let copy = Instruction::try_from(unsafe { CAbiRef::new(cinstruction.as_c()) }).unwrap();
+    assert_eq!(copy, instruction);
+
+    let meta = &instruction.accounts[0];
+    let cmeta = CAccountMeta::from_account_meta(meta);
+    assert_eq!(
+        AccountMeta::try_from(unsafe { CAbiRef::new(&cmeta) }).unwrap(),
+        *meta
+    );
+}
+
+#[test]
+fn account_infos_round_trip() {
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (mut lamports, mut data) = (42, [1u8, 2, 3]);
+    let caccount_info = CAccountInfo {
+        key: &key as *const Pubkey as *const CPubkey,
+        lamports: &mut lamports,
+        data_len: 3,
+        data: data.as_mut_ptr(),
+        owner: &owner as *const Pubkey as *const CPubkey,
+        rent_epoch: 7,
+        is_signer: true,
+        is_writable: false,
+        executable: true,
+        original_data_len: 3,
+        data_capacity: 3,
+    };
+    let account_info = AccountInfo::try_from(unsafe { CAbiRef::new(&caccount_info) }).unwrap();
+    assert_eq!(
+        (
+            *account_info.key,
+            *account_info.owner,
+            account_info.lamports()
+        ),
+        (key, owner, 42)
+    );
+    assert_eq!(*account_info.data.borrow(), [1, 2, 3]);
+    assert_eq!(account_info.rent_epoch, 7);
+    assert!(account_info.is_signer && !account_info.is_writable && account_info.executable);
+
+    let bad = CAccountInfo {
+        owner: std::ptr::null(),
+        ..caccount_info
+    };
+    assert_eq!(
+        AccountInfo::try_from(unsafe { CAbiRef::new(&bad) }).unwrap_err(),
+        CAbiError::NullPointer("CAccountInfo::owner")
+    );
+    let bad = CAccountInfo {
+        data_len: MAX_PERMITTED_DATA_LENGTH + 1,
+        ..caccount_info
+    };
+    assert_eq!(
+        AccountInfo::try_from(unsafe { CAbiRef::new(&bad) }).unwrap_err(),
+        CAbiError::TooLong {
+            field: "CAccountInfo::data_len",
+            len: MAX_PERMITTED_DATA_LENGTH + 1,
+            max: MAX_PERMITTED_DATA_LENGTH,
+        }
+    );
+    // Empty data may be null.
+    let empty = CAccountInfo {
+        data: std::ptr::null_mut(),
+        data_len: 0,
+        ..caccount_info
+    };
+    let account_info = AccountInfo::try_from(unsafe { CAbiRef::new(&empty) }).unwrap();
+    assert!(account_info.data_is_empty());
+}
+
+#[test]
+fn null_pointers_are_rejected() {
+    let instruction = instruction();
+    let cinstruction = CInstructionBuf::try_from(&instruction).unwrap();
+
+    let bad = CInstruction {
+        program_id: std::ptr::null(),
+        ..*cinstruction.as_c()
+    };
+    assert_eq!(
+        Instruction::try_from(unsafe { CAbiRef::new(&bad) }).unwrap_err(),
+        CAbiError::NullPointer("CInstruction::program_id")
+    );
+    let bad = CInstruction {
+        data: std::ptr::null(),
+        ..*cinstruction.as_c()
+    };
+    assert_eq!(
+        Instruction::try_from(unsafe { CAbiRef::new(&bad) }).unwrap_err(),
+        CAbiError::NullPointer("CInstruction::data")
+    );
+    // Null is fine for what is empty.
+    let empty = CInstruction {
+        accounts: std::ptr::null(),
+        accounts_len: 0,
+        data: std::ptr::null(),
+        data_len: 0,
+        ..*cinstruction.as_c()
+    };
+    let copy = Instruction::try_from(unsafe { CAbiRef::new(&empty) }).unwrap();
+    assert!(copy.accounts.is_empty() && copy.data.is_empty());
+
+    let metas = [CAccountMeta {
+        pubkey: std::ptr::null(),
+        is_writable: false,
+        is_signer: false,
+    }];
+    assert_eq!(
+        AccountMeta::try_from(unsafe { CAbiRef::new(&metas[0]) }).unwrap_err(),
+        CAbiError::NullPointer("CAccountMeta::pubkey")
+    );
+    let bad = CInstruction {
+        accounts: metas.as_ptr(),
+        accounts_len: 1,
+        ..*cinstruction.as_c()
+    };
+    assert_eq!(
+        Instruction::try_from(unsafe { CAbiRef::new(&bad) }).unwrap_err(),
+        CAbiError::NullPointer("CAccountMeta::pubkey")
+    );
+}
+
+#[test]
+fn lengths_over_the_cpi_limits_are_rejected() {
+    let mut instruction = instruction();
+    instruction.data = vec![0; MAX_CPI_INSTRUCTION_DATA_LEN + 1];
+    assert_eq!(
+        CInstructionBuf::try_from(&instruction).err(),
+        Some(CAbiError::TooLong {
+            field: "Instruction::data",
+            len: MAX_CPI_INSTRUCTION_DATA_LEN as u64 + 1,
+            max: MAX_CPI_INSTRUCTION_DATA_LEN as u64,
+        })
+    );
+    instruction.data.clear();
+    instruction.accounts = vec![AccountMeta::default(); MAX_CPI_INSTRUCTION_ACCOUNTS + 1];
+    assert!(matches!(
+        CInstructionBuf::try_from(&instruction),
+        Err(CAbiError::TooLong {
+            field: "Instruction::accounts",
+            ..
+        })
+    ));
+
+    let instruction = self::instruction();
+    let cinstruction = CInstructionBuf::try_from(&instruction).unwrap();
+    let bad = CInstruction {
+        data_len: MAX_CPI_INSTRUCTION_DATA_LEN as u64 + 1,
+        ..*cinstruction.as_c()
+    };
+    assert!(matches!(
+        Instruction::try_from(unsafe { CAbiRef::new(&bad) }),
+        Err(CAbiError::TooLong {
+            field: "CInstruction::data_len",
+            ..
+        })
+    ));
+    let bad = CInstruction {
+        accounts_len: MAX_CPI_INSTRUCTION_ACCOUNTS as u64 + 1,
+        ..*cinstruction.as_c()
+    };
+    assert!(matches!(
+        Instruction::try_from(unsafe { CAbiRef::new(&bad) }),
+        Err(CAbiError::TooLong {
+            field: "CInstruction::accounts_len",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn program_errors_round_trip_with_the_runtimes_codes() {
+    let errors = [
+        (ProgramError::Custom(0), loader::CUSTOM_ZERO),
+        (ProgramError::Custom(7), 7),
+        (ProgramError::InvalidArgument, loader::INVALID_ARGUMENT),
+        (
+            ProgramError::BorshIoError("Unknown".into()),
+            loader::BORSH_IO_ERROR,
+        ),
+        (
+            ProgramError::InvalidRealloc,
+            loader::INVALID_ACCOUNT_DATA_REALLOC,
+        ),
+        (
+            ProgramError::IncorrectAuthority,
+            loader::INCORRECT_AUTHORITY,
+        ),
+    ];
+    for (error, code) in errors {
+        assert_eq!(loader::program_error_to_u64(&error), code);
+        assert_eq!(app::program_error_from_u64(code), error);
+        assert_eq!(
+            app::program_result_from_u64(loader::program_result_to_u64(&Err(error.clone()))),
+            Err(error)
+        );
+    }
+    // Unknown codes keep their low bits as a custom error.
+    assert_eq!(
+        app::program_error_from_u64((99 << 32) | 5),
+        ProgramError::Custom(5)
+    );
+    assert_eq!(app::program_result_from_u64(0), Ok(()));
+}
@@ -0,0 +1,133 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    program_error::ProgramError, program_stubs::SyscallStubs, pubkey::Pubkey,
+};
+use solana_program_stubs::loader_side::serde_json::{json, Value};
+use solana_program_stubs_macro_tests::{app, enter_stubs, loader, run_program, set_loader_stubs};
+use std::sync::{Arc, Barrier};
+
+/// A top-level `sol_invoke_signed` record that leaves one account holding `data`.
+fn invoke_record(seq: u64, owner: &Pubkey, data: &str) -> Value {
+    json!({
+        "seq": seq,
+        "depth": 0,
+        "syscall": "sol_invoke_signed",
+        "args": {},
+        "result": 0,
+        "output": {
+            "account_infos": [{ "lamports": 5, "owner": owner.to_string(), "data": data }],
+        },
+    })
+}
+
+#[test]
+fn replayed_cpis_resize_account_data_without_leaking() {
+    let _arena = loader::enter_instruction_arena();
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let replay = loader::ReplaySyscallStubs::new(vec![
+        invoke_record(0, &owner, "0102"),
+        invoke_record(1, &owner, "010203040506"),
+    ]);
+    let (mut lamports, mut data) = (0, [9u8; 4]);
+    let data_ptr = data.as_ptr();
+    let account_info = AccountInfo::new(
+        &key,
+        false,
+        true,
+        &mut lamports,
+        &mut data,
+        &owner,
+        false,
+        0,
+    );
+    let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+    let accounts = [account_info];
+
+    replay
+        .sol_invoke_signed(&instruction, &accounts, &[])
+        .unwrap();
+    assert_eq!(**accounts[0].lamports.borrow(), 5);
+    // Shrunk in place.
+    assert_eq!(*accounts[0].data.borrow(), [1, 2]);
+    assert_eq!(accounts[0].data.borrow().as_ptr(), data_ptr);
+
+    replay
+        .sol_invoke_signed(&instruction, &accounts, &[])
+        .unwrap();
+    // Grown into the instruction's arena.
+    assert_eq!(*accounts[0].data.borrow(), [1, 2, 3, 4, 5, 6]);
+
+    // The trace has ended: the syscall fails instead of panicking.
+    assert_eq!(
+        replay.sol_invoke_signed(&instruction, &accounts, &[]),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(replay.sol_get_stack_height(), 0);
+    assert_eq!(replay.remaining(), 0);
+}
+
+#[test]
+fn diverging_programs_are_stopped() {
+    set_loader_stubs();
+    let _stubs = enter_stubs(Box::new(loader::ReplaySyscallStubs::new(vec![])));
+    assert_eq!(
+        run_program(&[app::GET_CLOCK]),
+        Err(loader::ProgramAbort::ReplayDiverged(
+            "replay: sol_get_clock_sysvar was called after the trace ended".into()
+        ))
+    );
+}
+
+/// Waits at the barrier once when entering `sol_invoke_signed` and once
+/// before returning.
+struct BlockingCpi(Arc<Barrier>);
+
+impl SyscallStubs for BlockingCpi {
+    fn sol_invoke_signed(
+        &self,
+        _instruction: &Instruction,
+        _account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.0.wait();
+        self.0.wait();
+        Ok(())
+    }
+    fn sol_log(&self, _message: &str) {}
+}
+
+impl loader::LoaderSyscallStubs for BlockingCpi {}
+
+#[test]
+fn calls_made_during_another_threads_cpi_are_top_level() {
+    let barrier = Arc::new(Barrier::new(2));
+    let tracer = loader::TracingSyscallStubs::new(Box::new(BlockingCpi(barrier.clone())));
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+            tracer.sol_invoke_signed(&instruction, &[], &[]).unwrap();
+        });
+        barrier.wait();
+        tracer.sol_log("during the cpi");
+        barrier.wait();
+    });
+    let records: Vec<_> = tracer
+        .records()
+        .iter()
+        .map(|record| {
+            (
+                record["syscall"].clone(),
+                record["seq"].clone(),
+                record["depth"].clone(),
+            )
+        })
+        .collect();
+    // The CPI took its `seq` before the log, but is recorded once it returns.
+    assert_eq!(
+        records,
+        [
+            (json!("sol_log"), json!(1), json!(0)),
+            (json!("sol_invoke_signed"), json!(0), json!(0)),
+        ]
+    );
+}
@@ -0,0 +1,314 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    program_error::ProgramError, program_stubs::SyscallStubs, pubkey::Pubkey,
+};
+use solana_program_stubs_macro_tests::{app, enter_stubs, loader, run_program, set_loader_stubs};
+use std::sync::{Arc, Mutex};
+
+/// Records the arguments each syscall reached the loader with.
+#[derive(Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl SyscallStubs for Recorder {
+    fn sol_log(&self, message: &str) {
+        self.0.lock().unwrap().push(format!("log {message}"));
+    }
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        self.0.lock().unwrap().push(format!("data {fields:?}"));
+    }
+}
+
+impl loader::LoaderSyscallStubs for Recorder {
+    fn sol_sha256(&self, vals: &[&[u8]], hash_result: &mut [u8; 32]) -> u64 {
+        self.0.lock().unwrap().push(format!("sha256 {vals:?}"));
+        hash_result.fill(7);
+        0
+    }
+    fn sol_create_program_address(
+        &self,
+        seeds: &[&[u8]],
+        program_id: &Pubkey,
+        address: &mut Pubkey,
+    ) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("create_program_address {:?}", seeds.last()));
+        match Pubkey::create_program_address(seeds, program_id) {
+            Ok(pda) => {
+                *address = pda;
+                0
+            }
+            Err(_) => 1,
+        }
+    }
+}
+
+fn recorder() -> (Arc<Mutex<Vec<String>>>, loader::StubContextGuard) {
+    set_loader_stubs();
+    let recorder = Recorder::default();
+    let calls = recorder.0.clone();
+    (calls, enter_stubs(Box::new(recorder)))
+}
+
+#[test]
+fn slices_reach_the_loader() {
+    let (calls, _stubs) = recorder();
+    let program = app::SolAppSyscallStubs::installed().unwrap();
+    program.sol_log("hello");
+    program.sol_log_data(&[b"ab", b"c"]);
+    let mut hash = [0u8; 32];
+    assert_eq!(program.sol_sha256(&[b"abc", b""], &mut hash), 0);
+    assert_eq!(hash, [7; 32]);
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [
+            "log hello",
+            "data [[97, 98], [99]]",
+            "sha256 [[97, 98, 99], []]",
+        ]
+    );
+}
+
+#[test]
+fn program_addresses() {
+    let (_, _stubs) = recorder();
+    let program = app::SolAppSyscallStubs::installed().unwrap();
+    let program_id = Pubkey::new_unique();
+    let (expected, expected_bump) = Pubkey::find_program_address(&[b"seed"], &program_id);
+    let (mut address, mut bump) = (Pubkey::default(), 0);
+    assert_eq!(
+        program.sol_try_find_program_address(&[b"seed"], &program_id, &mut address, &mut bump),
+        0
+    );
+    assert_eq!((address, bump), (expected, expected_bump));
+    let mut address = Pubkey::default();
+    assert_eq!(
+        program.sol_create_program_address(&[b"seed", &[bump]], &program_id, &mut address),
+        0
+    );
+    assert_eq!(address, expected);
+}
+
+#[test]
+fn unimplemented_syscalls_fail() {
+    let (calls, _stubs) = recorder();
+    let program = app::SolAppSyscallStubs::installed().unwrap();
+    assert_eq!(program.sol_blake3(&[b"abc"], &mut [0; 32]), 1);
+    assert_eq!(
+        *calls.lock().unwrap(),
+        ["log SyscallStubs: sol_blake3() not available"]
+    );
+}
+
+#[test]
+fn oversized_return_data_aborts_through_the_loader() {
+    let (calls, _stubs) = recorder();
+    assert_eq!(
+        run_program(&[app::SET_OVERSIZED_RETURN_DATA]),
+        Err(loader::ProgramAbort::ReturnDataTooLarge { len: 1025 })
+    );
+    assert!(calls.lock().unwrap().is_empty());
+}
+
+#[test]
+fn syscalls_over_budget_fail_without_running() {
+    let recorder = Recorder::default();
+    let calls = recorder.0.clone();
+    let metered = loader::MeteredSyscallStubs::new(Box::new(recorder)).with_budget(150);
+    metered.sol_log("charged");
+    metered.sol_log("over budget");
+    assert_eq!(metered.remaining(), 0);
+    assert_eq!(
+        loader::LoaderSyscallStubs::sol_sha256(&metered, &[b"abc"], &mut [0; 32]),
+        1
+    );
+    assert_eq!(*calls.lock().unwrap(), ["log charged"]);
+    // Outside `invoke_entrypoint` there is no program to abort.
+    assert!(!loader::is_program_aborted());
+}
+
+#[test]
+fn program_address_searches_are_charged_before_each_attempt() {
+    let program_id = Pubkey::new_from_array([1; 32]);
+    let seeds: &[&[u8]] = &[b"seed"];
+    let (expected, expected_bump) = Pubkey::find_program_address(seeds, &program_id);
+    let attempts = (u8::MAX - expected_bump) as u64 + 1;
+    let units = loader::ComputeCosts::default().create_program_address_units;
+
+    let search = |budget| {
+        let recorder = Recorder::default();
+        let calls = recorder.0.clone();
+        let metered = loader::MeteredSyscallStubs::new(Box::new(recorder)).with_budget(budget);
+        let (mut address, mut bump) = (Pubkey::default(), 0);
+        let result = loader::LoaderSyscallStubs::sol_try_find_program_address(
+            &metered,
+            seeds,
+            &program_id,
+            &mut address,
+            &mut bump,
+        );
+        let attempts = calls.lock().unwrap().len() as u64;
+        (result, address, bump, attempts, metered.remaining())
+    };
+    assert_eq!(
+        search(units * attempts),
+        (0, expected, expected_bump, attempts, 0)
+    );
+    // The last attempt isn't made when the budget can't pay for it.
+    let (result, _, _, made, remaining) = search(units * attempts - 1);
+    assert_eq!((result, made, remaining), (1, attempts - 1, 0));
+}
+
+/// Swaps the first account's data for another buffer of the same length and
+/// grows the second past what its caller has room for.
+struct Resizer;
+
+impl SyscallStubs for Resizer {
+    fn sol_invoke_signed(
+        &self,
+        _instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let arena = loader::current_instruction_arena();
+        *account_infos[0].data.borrow_mut() = unsafe { &mut *arena.alloc_data(vec![1; 4]) };
+        *account_infos[1].data.borrow_mut() = unsafe { &mut *arena.alloc_data(vec![1; 8]) };
+        Ok(())
+    }
+}
+
+impl loader::LoaderSyscallStubs for Resizer {}
+
+#[test]
+fn failed_cpis_write_no_account_back() {
+    let _stubs = enter_stubs(Box::new(Resizer));
+    let _arena = loader::enter_instruction_arena();
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut lamports = [0u64; 2];
+    let mut data = [[0u8; 4]; 2];
+    let mut caccount_infos: Vec<_> = lamports
+        .iter_mut()
+        .zip(&mut data)
+        .map(|(lamports, data)| loader::CAccountInfo {
+            key: &key as *const Pubkey as *const loader::CPubkey,
+            lamports,
+            data_len: 4,
+            data: data.as_mut_ptr(),
+            owner: &owner as *const Pubkey as *const loader::CPubkey,
+            rent_epoch: 0,
+            is_signer: false,
+            is_writable: true,
+            executable: false,
+            original_data_len: 4,
+            data_capacity: 6,
+        })
+        .collect();
+    let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+    let cinstruction = loader::CInstructionBuf::try_from(&instruction).unwrap();
+    let result = loader::sol_invoke_signed_c(
+        cinstruction.as_ptr(),
+        caccount_infos.as_mut_ptr() as *const u8,
+        2,
+        std::ptr::null(),
+        0,
+    );
+    assert_eq!(
+        result,
+        loader::program_error_to_u64(&ProgramError::InvalidRealloc)
+    );
+    assert_eq!(data, [[0; 4]; 2]);
+    assert!(caccount_infos.iter().all(|cai| cai.data_len == 4));
+}
+
+/// Resizes the first account's data to the given length.
+struct SetDataLen(usize);
+
+impl SyscallStubs for SetDataLen {
+    fn sol_invoke_signed(
+        &self,
+        _instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let mut data = account_infos[0].data.borrow_mut();
+        if self.0 <= data.len() {
+            let resized = &mut std::mem::take(&mut *data)[..self.0];
+            *data = resized;
+        } else {
+            let arena = loader::current_instruction_arena();
+            *data = unsafe { &mut *arena.alloc_data(vec![1; self.0]) };
+        }
+        Ok(())
+    }
+}
+
+impl loader::LoaderSyscallStubs for SetDataLen {}
+
+#[test]
+fn cpis_shrink_accounts_built_natively() {
+    set_loader_stubs();
+    let _arena = loader::enter_instruction_arena();
+    let program = app::SolAppSyscallStubs::installed().unwrap();
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (mut lamports, mut data) = (0, [1u8, 2, 3, 4]);
+    let account_info = AccountInfo::new(
+        &key,
+        false,
+        true,
+        &mut lamports,
+        &mut data,
+        &owner,
+        false,
+        0,
+    );
+    let account_infos = [account_info];
+    let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+
+    // Only the runtime's serialized accounts have room to grow.
+    let stubs = enter_stubs(Box::new(SetDataLen(5)));
+    assert_eq!(
+        program.sol_invoke_signed(&instruction, &account_infos, &[]),
+        Err(ProgramError::InvalidRealloc)
+    );
+    drop(stubs);
+    assert_eq!(*account_infos[0].data.borrow(), [1, 2, 3, 4]);
+
+    let _stubs = enter_stubs(Box::new(SetDataLen(2)));
+    program
+        .sol_invoke_signed(&instruction, &account_infos, &[])
+        .unwrap();
+    assert_eq!(*account_infos[0].data.borrow(), [1, 2]);
+    drop(account_infos);
+    assert_eq!(data, [1, 2, 0, 0]);
+}
+
+#[test]
+fn sysvar_account_data_is_served_in_the_getters_layout() {
+    let rent = loader::RentSysvar {
+        lamports_per_byte_year: 1,
+        exemption_threshold: 2.5,
+        burn_percent: 3,
+    };
+    let stubs = loader::NativeSyscallStubs::default()
+        .with_sysvar(loader::RENT_SYSVAR_ID, rent.to_account_data())
+        .with_sysvar(loader::CLOCK_SYSVAR_ID, vec![7; 48]);
+    // The getter writes no more than the runtime's layout, not the whole account.
+    let mut var = [0xffu8; loader::RENT_SYSVAR_SIZE + 8];
+    assert_eq!(stubs.sol_get_rent_sysvar(var.as_mut_ptr()), 0);
+    assert_eq!(var[..loader::RENT_SYSVAR_SIZE], rent.to_bytes());
+    assert_eq!(var[loader::RENT_SYSVAR_SIZE..], [0xff; 8]);
+
+    let mut var = [0u8; loader::CLOCK_SYSVAR_SIZE];
+    assert_eq!(
+        stubs.sol_get_clock_sysvar(var.as_mut_ptr()),
+        loader::UNSUPPORTED_SYSVAR
+    );
+    // `sol_get_sysvar` still reads the account data as set.
+    let mut window = [0u8; 8];
+    assert_eq!(
+        stubs.sol_get_sysvar(loader::CLOCK_SYSVAR_ID.as_ptr(), window.as_mut_ptr(), 40, 8),
+        0
+    );
+    assert_eq!(window, [7; 8]);
+}
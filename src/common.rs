@@ -1,164 +1,443 @@
+pub mod abi;
+
 /// A macro providing the common types for both a Solana program and a loader of it.
+///
+/// The ABI itself lives in [`abi`] and is re-exported; only what depends on the
+/// caller's `solana_program` types (`ProgramError`, `Instruction`, `AccountInfo`, ...)
+/// is expanded in place.
 #[macro_export]
 macro_rules! common_stub_types {
     () => {
-        pub const PUBKEY_BYTES: usize = 32;
-        #[repr(C)]
-        pub struct CPubkey(pub [u8; PUBKEY_BYTES]);
+        pub use $crate::common::abi::*;
+
+        /// Encodes `error` as the non-zero `u64` a failed call returns across
+        /// the stub boundary, as the runtime does. `BorshIoError`'s message
+        /// doesn't survive the trip.
+        pub fn program_error_to_u64(error: &ProgramError) -> u64 {
+            error.clone().into()
+        }
+
+        /// Decodes a non-zero code produced by [`program_error_to_u64`]. Codes
+        /// with upper bits set that name no builtin error keep their low 32
+        /// bits as a custom code, as the runtime does.
+        pub fn program_error_from_u64(code: u64) -> ProgramError {
+            code.into()
+        }
 
-        impl CPubkey {
-            pub const fn as_array(&self) -> &[u8; PUBKEY_BYTES] {
-                &self.0
+        /// Encodes a call's outcome, `0` standing for success.
+        pub fn program_result_to_u64(result: &Result<(), ProgramError>) -> u64 {
+            match result {
+                Ok(()) => 0,
+                Err(error) => program_error_to_u64(error),
             }
         }
 
-        impl AsRef<[u8]> for CPubkey {
-            fn as_ref(&self) -> &[u8] {
-                &self.0[..]
+        /// Decodes a call's outcome encoded by [`program_result_to_u64`].
+        pub fn program_result_from_u64(code: u64) -> Result<(), ProgramError> {
+            match code {
+                0 => Ok(()),
+                code => Err(program_error_from_u64(code)),
             }
         }
+        /// Why a C ABI struct could not be read, or built from a solana type.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum CAbiError {
+            /// The named pointer was null where it must not be.
+            NullPointer(&'static str),
+            /// The named length is over the runtime's limit.
+            TooLong {
+                field: &'static str,
+                len: u64,
+                max: u64,
+            },
+        }
 
-        impl AsMut<[u8]> for CPubkey {
-            fn as_mut(&mut self) -> &mut [u8] {
-                &mut self.0[..]
+        impl std::fmt::Display for CAbiError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Self::NullPointer(field) => write!(f, "`{field}` is a null pointer"),
+                    Self::TooLong { field, len, max } => {
+                        write!(f, "`{field}` is {len} long, over the limit of {max}")
+                    }
+                }
             }
         }
 
-        impl From<[u8; 32]> for CPubkey {
-            #[inline]
-            fn from(from: [u8; 32]) -> Self {
-                Self(from)
+        impl std::error::Error for CAbiError {}
+
+        impl From<CAbiError> for ProgramError {
+            fn from(_: CAbiError) -> Self {
+                ProgramError::InvalidArgument
             }
         }
 
-        impl From<&[u8; 32]> for CPubkey {
-            #[inline]
-            fn from(from: &[u8; 32]) -> Self {
-                Self(*from)
+        impl CAbiError {
+            fn check_not_null<T>(ptr: *const T, field: &'static str) -> Result<(), Self> {
+                if ptr.is_null() {
+                    Err(Self::NullPointer(field))
+                } else {
+                    Ok(())
+                }
+            }
+
+            fn check_len(field: &'static str, len: u64, max: usize) -> Result<(), Self> {
+                if len > max as u64 {
+                    Err(Self::TooLong {
+                        field,
+                        len,
+                        max: max as u64,
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+
+            /// Reads `len` elements at `ptr`, which may only be null when `len` is 0.
+            unsafe fn c_slice<'a, T>(
+                ptr: *const T,
+                len: u64,
+                field: &'static str,
+            ) -> Result<&'a [T], Self> {
+                if len == 0 {
+                    return Ok(&[]);
+                }
+                Self::check_not_null(ptr, field)?;
+                Ok(std::slice::from_raw_parts(ptr, len as usize))
             }
         }
 
-        impl PartialEq<[u8; 32]> for CPubkey {
-            fn eq(&self, other: &[u8; 32]) -> bool {
-                &self.0 == other
+        /// A C ABI struct whose pointers the caller vouches for, so that it can
+        /// be converted to its solana type with `TryFrom`, which still checks
+        /// them for nulls and lengths.
+        #[derive(Debug, Clone, Copy)]
+        pub struct CAbiRef<'a, T>(&'a T);
+
+        impl<'a, T> CAbiRef<'a, T> {
+            /// # Safety
+            ///
+            /// Every non-null pointer in `value` must be valid for its length for
+            /// as long as the converted value is used, with the same guarantees
+            /// as the struct's `view`, `to_account_meta` or `to_account_info`.
+            pub unsafe fn new(value: &'a T) -> Self {
+                Self(value)
             }
         }
 
-        #[repr(C)]
-        pub struct CProcessedSiblingInstruction {
-            pub data_len: u64,
-            pub accounts_len: u64,
+        impl TryFrom<CAbiRef<'_, CAccountMeta>> for AccountMeta {
+            type Error = CAbiError;
+
+            fn try_from(meta: CAbiRef<'_, CAccountMeta>) -> Result<Self, Self::Error> {
+                unsafe { meta.0.to_account_meta() }
+            }
         }
 
-        #[repr(C)]
-        #[derive(Clone)]
-        pub struct CAccountMeta {
-            pub pubkey: *const CPubkey,
-            pub is_writable: bool,
-            pub is_signer: bool,
+        impl TryFrom<CAbiRef<'_, CInstruction>> for Instruction {
+            type Error = CAbiError;
+
+            fn try_from(instruction: CAbiRef<'_, CInstruction>) -> Result<Self, Self::Error> {
+                unsafe { instruction.0.view() }.map(|view| view.to_instruction())
+            }
         }
 
-        impl Default for CAccountMeta {
-            fn default() -> Self {
+        impl<'a> TryFrom<CAbiRef<'a, CAccountInfo>> for AccountInfo<'a> {
+            type Error = CAbiError;
+
+            fn try_from(account_info: CAbiRef<'a, CAccountInfo>) -> Result<Self, Self::Error> {
+                unsafe { account_info.0.to_account_info() }
+            }
+        }
+
+        /// Conversions between [`CAccountMeta`] and `AccountMeta`.
+        pub trait CAccountMetaExt {
+            /// Builds the meta of `account_meta`, pointing at its pubkey.
+            fn from_account_meta(account_meta: &AccountMeta) -> Self;
+
+            /// Reads the meta back as an `AccountMeta`.
+            ///
+            /// # Safety
+            ///
+            /// `pubkey`, if not null, must point to a valid pubkey.
+            unsafe fn to_account_meta(&self) -> Result<AccountMeta, CAbiError>;
+        }
+
+        impl CAccountMetaExt for CAccountMeta {
+            fn from_account_meta(account_meta: &AccountMeta) -> Self {
                 Self {
-                    pubkey: &CPubkey::from([0u8; 32]),
-                    is_writable: false,
-                    is_signer: false,
+                    pubkey: &account_meta.pubkey as *const Pubkey as *const CPubkey,
+                    is_writable: account_meta.is_writable,
+                    is_signer: account_meta.is_signer,
                 }
             }
+
+            unsafe fn to_account_meta(&self) -> Result<AccountMeta, CAbiError> {
+                CAbiError::check_not_null(self.pubkey, "CAccountMeta::pubkey")?;
+                Ok(AccountMeta {
+                    pubkey: Pubkey::new_from_array(*(*self.pubkey).as_array()),
+                    is_signer: self.is_signer,
+                    is_writable: self.is_writable,
+                })
+            }
         }
 
-        #[repr(C)]
-        #[derive(Debug)]
-        pub struct CAccountInfo {
-            // Public key of the account.
-            pub key: *const CPubkey,
-
-            // Number of lamports owned by this account.
-            pub lamports: *const u64,
-
-            // Length of data in bytes.
-            pub data_len: u64,
-
-            // On-chain data within this account.
-            pub data: *const u8,
-
-            // Program that owns this account.
-            pub owner: *const CPubkey,
-
-            // The epoch at which this account will next owe rent.
-            pub rent_epoch: u64,
-
-            // Transaction was signed by this account's key?
-            pub is_signer: bool,
-
-            // Is the account writable?
-            pub is_writable: bool,
-
-            // This account's data contains a loaded program (and is now read-only).
-            pub executable: bool,
-        }
-
-        #[repr(C)]
-        #[derive(Debug)]
-        pub struct CInstruction {
-            /// Public key of the program.
-            pub program_id: *const CPubkey,
-
-            /// Accounts expected by the program instruction.
-            pub accounts: *const CAccountMeta,
-
-            /// Number of accounts expected by the program instruction.
-            pub accounts_len: u64,
-
-            /// Data expected by the program instruction.
-            pub data: *const u8,
-
-            /// Length of the data expected by the program instruction.
-            pub data_len: u64,
-        }
-
-        #[repr(C)]
-        pub struct SyscallStubsApi {
-            pub sol_log_: extern "C" fn(message: *const u8, len: u64),
-            pub sol_log_compute_units_: extern "C" fn(),
-            pub sol_remaining_compute_units: extern "C" fn() -> u64,
-            pub sol_invoke_signed_c: extern "C" fn(
-                instruction_addr: *const u8,
-                account_infos_addr: *const u8,
-                account_infos_len: u64,
-                signers_seeds_addr: *const u8,
-                signers_seeds_len: u64,
-            ) -> u64,
-            pub sol_get_clock_sysvar: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_epoch_schedule_sysvar: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_fees_sysvar: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_rent_sysvar: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_last_restart_slot: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_sysvar: extern "C" fn(
-                sysvar_id_addr: *const u8,
-                result: *mut u8,
-                offset: u64,
-                length: u64,
-            ) -> u64,
-            pub sol_memcpy_: extern "C" fn(dst: *mut u8, src: *const u8, n: u64),
-            pub sol_memmove_: extern "C" fn(dst: *mut u8, src: *const u8, n: u64),
-            pub sol_memcmp_: extern "C" fn(s1: *const u8, s2: *const u8, n: u64, result: *mut i32),
-            pub sol_memset_: extern "C" fn(s: *mut u8, c: u8, n: u64),
-            pub sol_get_return_data:
-                extern "C" fn(data: *mut u8, length: u64, program_id: *mut CPubkey) -> u64,
-            pub sol_set_return_data: extern "C" fn(data: *const u8, length: u64),
-            pub sol_log_data: extern "C" fn(data: *const u8, data_len: u64),
-            pub sol_get_processed_sibling_instruction: extern "C" fn(
-                index: u64,
-                meta: *mut CProcessedSiblingInstruction,
-                program_id: *mut CPubkey,
-                data: *mut u8,
-                accounts: *mut CAccountMeta,
-            ) -> u64,
-            pub sol_get_stack_height: extern "C" fn() -> u64,
-            pub sol_get_epoch_rewards_sysvar: extern "C" fn(addr: *mut u8) -> u64,
-            pub sol_get_epoch_stake: extern "C" fn(vote_address: *const u8) -> u64,
+        /// A [`CInstruction`] read in place, with its pointers checked.
+        #[derive(Clone, Copy)]
+        pub struct CInstructionView<'a> {
+            pub program_id: &'a Pubkey,
+            pub accounts: &'a [CAccountMeta],
+            pub data: &'a [u8],
+        }
+
+        impl CInstructionView<'_> {
+            /// The account metas as `AccountMeta`s.
+            pub fn account_metas(&self) -> Vec<AccountMeta> {
+                self.accounts
+                    .iter()
+                    // The pubkeys were checked by `CInstructionExt::view`.
+                    .map(|meta| unsafe { meta.to_account_meta() }.unwrap())
+                    .collect()
+            }
+
+            /// Copies the instruction out.
+            pub fn to_instruction(&self) -> Instruction {
+                Instruction {
+                    program_id: *self.program_id,
+                    accounts: self.account_metas(),
+                    data: self.data.to_vec(),
+                }
+            }
+        }
+
+        impl From<CInstructionView<'_>> for Instruction {
+            fn from(view: CInstructionView<'_>) -> Self {
+                view.to_instruction()
+            }
+        }
+
+        /// Reading a [`CInstruction`] as an `Instruction`.
+        pub trait CInstructionExt {
+            /// Borrows the program id, account metas and data the instruction
+            /// points to, after checking its pointers and lengths.
+            ///
+            /// # Safety
+            ///
+            /// Every non-null pointer must be valid for reads of its length for
+            /// as long as the view is used, including the account metas' pubkeys.
+            unsafe fn view(&self) -> Result<CInstructionView<'_>, CAbiError>;
+        }
+
+        impl CInstructionExt for CInstruction {
+            unsafe fn view(&self) -> Result<CInstructionView<'_>, CAbiError> {
+                CAbiError::check_not_null(self.program_id, "CInstruction::program_id")?;
+                CAbiError::check_len(
+                    "CInstruction::accounts_len",
+                    self.accounts_len,
+                    MAX_CPI_INSTRUCTION_ACCOUNTS,
+                )?;
+                CAbiError::check_len(
+                    "CInstruction::data_len",
+                    self.data_len,
+                    MAX_CPI_INSTRUCTION_DATA_LEN,
+                )?;
+                let accounts =
+                    CAbiError::c_slice(self.accounts, self.accounts_len, "CInstruction::accounts")?;
+                for meta in accounts {
+                    CAbiError::check_not_null(meta.pubkey, "CAccountMeta::pubkey")?;
+                }
+                Ok(CInstructionView {
+                    program_id: &*(self.program_id as *const Pubkey),
+                    accounts,
+                    data: CAbiError::c_slice(self.data, self.data_len, "CInstruction::data")?,
+                })
+            }
+        }
+
+        /// A [`CInstruction`] built from an `Instruction`, owning the account
+        /// metas it points to. The program id, pubkeys and data stay borrowed
+        /// from the `Instruction`.
+        pub struct CInstructionBuf<'a> {
+            instruction: CInstruction,
+            _accounts: Vec<CAccountMeta>,
+            _borrow: std::marker::PhantomData<&'a Instruction>,
+        }
+
+        impl CInstructionBuf<'_> {
+            pub fn as_c(&self) -> &CInstruction {
+                &self.instruction
+            }
+
+            /// The address to pass as `instruction_addr` to `sol_invoke_signed_c`.
+            pub fn as_ptr(&self) -> *const u8 {
+                &self.instruction as *const CInstruction as *const u8
+            }
+        }
+
+        impl<'a> TryFrom<&'a Instruction> for CInstructionBuf<'a> {
+            type Error = CAbiError;
+
+            /// Fails when the instruction has more accounts or data than a CPI
+            /// may carry.
+            fn try_from(instruction: &'a Instruction) -> Result<Self, Self::Error> {
+                CAbiError::check_len(
+                    "Instruction::accounts",
+                    instruction.accounts.len() as u64,
+                    MAX_CPI_INSTRUCTION_ACCOUNTS,
+                )?;
+                CAbiError::check_len(
+                    "Instruction::data",
+                    instruction.data.len() as u64,
+                    MAX_CPI_INSTRUCTION_DATA_LEN,
+                )?;
+                let accounts: Vec<_> = instruction
+                    .accounts
+                    .iter()
+                    .map(CAccountMeta::from_account_meta)
+                    .collect();
+                Ok(Self {
+                    instruction: CInstruction {
+                        program_id: &instruction.program_id as *const Pubkey as *const CPubkey,
+                        accounts: accounts.as_ptr(),
+                        accounts_len: accounts.len() as u64,
+                        data: instruction.data.as_ptr(),
+                        data_len: instruction.data.len() as u64,
+                    },
+                    _accounts: accounts,
+                    _borrow: std::marker::PhantomData,
+                })
+            }
+        }
+
+        /// Reading a [`CAccountInfo`] as an `AccountInfo`.
+        pub trait CAccountInfoExt {
+            /// Builds an `AccountInfo` over the key, lamports, data and owner the
+            /// struct points to, after checking its pointers and lengths.
+            ///
+            /// # Safety
+            ///
+            /// Every non-null pointer must be valid for its length for as long as
+            /// the `AccountInfo` is used, and nothing else may access the lamports
+            /// and data meanwhile.
+            unsafe fn to_account_info(&self) -> Result<AccountInfo<'_>, CAbiError>;
+        }
+
+        impl CAccountInfoExt for CAccountInfo {
+            unsafe fn to_account_info(&self) -> Result<AccountInfo<'_>, CAbiError> {
+                CAbiError::check_not_null(self.key, "CAccountInfo::key")?;
+                CAbiError::check_not_null(self.lamports, "CAccountInfo::lamports")?;
+                CAbiError::check_not_null(self.owner, "CAccountInfo::owner")?;
+                CAbiError::check_len(
+                    "CAccountInfo::data_len",
+                    self.data_len,
+                    MAX_PERMITTED_DATA_LENGTH as usize,
+                )?;
+                let data: &mut [u8] = if self.data_len == 0 {
+                    &mut []
+                } else {
+                    CAbiError::check_not_null(self.data, "CAccountInfo::data")?;
+                    std::slice::from_raw_parts_mut(self.data as *mut u8, self.data_len as usize)
+                };
+                Ok(AccountInfo {
+                    key: &*(self.key as *const Pubkey),
+                    lamports: std::rc::Rc::new(std::cell::RefCell::new(
+                        &mut *(self.lamports as *mut u64),
+                    )),
+                    data: std::rc::Rc::new(std::cell::RefCell::new(data)),
+                    owner: &*(self.owner as *const Pubkey),
+                    rent_epoch: self.rent_epoch,
+                    is_signer: self.is_signer,
+                    is_writable: self.is_writable,
+                    executable: self.executable,
+                })
+            }
+        }
+
+        /// [`CAccountInfo`]s built from `AccountInfo`s, pointing at their keys,
+        /// lamports, data and owners.
+        pub struct CAccountInfoBuf<'a> {
+            account_infos: Vec<CAccountInfo>,
+            _borrow: std::marker::PhantomData<&'a [CAccountInfo]>,
+        }
+
+        impl<'a> CAccountInfoBuf<'a> {
+            /// Builds the structs for `account_infos`, reading each account's
+            /// original data length the way `AccountInfo::realloc` does.
+            ///
+            /// # Safety
+            ///
+            /// The accounts must have been serialized by the runtime, or by a
+            /// loader mimicking it: the original data length precedes each key
+            /// and `MAX_PERMITTED_DATA_INCREASE` spare bytes follow each data.
+            pub unsafe fn from_serialized(account_infos: &'a [AccountInfo<'_>]) -> Self {
+                Self::from_accounts(account_infos, |_| true)
+            }
+
+            /// Builds the structs for `account_infos`, reading the original data
+            /// length of the accounts `serialized` picks the way
+            /// [`from_serialized`](Self::from_serialized) does. The others, such as
+            /// accounts built with `AccountInfo::new`, can't grow: their original
+            /// length is their current one.
+            ///
+            /// # Safety
+            ///
+            /// The accounts `serialized` picks must be laid out as
+            /// [`from_serialized`](Self::from_serialized) requires.
+            pub unsafe fn from_accounts(
+                account_infos: &'a [AccountInfo<'_>],
+                serialized: impl Fn(&AccountInfo<'_>) -> bool,
+            ) -> Self {
+                let account_infos = account_infos
+                    .iter()
+                    .map(|account_info| {
+                        let (original_data_len, data_capacity) = if serialized(account_info) {
+                            let original_data_len = account_info.original_data_len() as u64;
+                            let data_capacity = (original_data_len
+                                + MAX_PERMITTED_DATA_INCREASE as u64)
+                                .min(MAX_PERMITTED_DATA_LENGTH);
+                            (original_data_len, data_capacity)
+                        } else {
+                            let data_len = account_info.data_len() as u64;
+                            (data_len, data_len)
+                        };
+                        let mut lamports = account_info.lamports.borrow_mut();
+                        let mut data = account_info.data.borrow_mut();
+                        CAccountInfo {
+                            key: account_info.key as *const Pubkey as *const CPubkey,
+                            lamports: &mut **lamports as *mut u64,
+                            data_len: data.len() as u64,
+                            data: data.as_mut_ptr(),
+                            owner: account_info.owner as *const Pubkey as *const CPubkey,
+                            rent_epoch: account_info.rent_epoch,
+                            is_signer: account_info.is_signer,
+                            is_writable: account_info.is_writable,
+                            executable: account_info.executable,
+                            original_data_len,
+                            data_capacity,
+                        }
+                    })
+                    .collect();
+                Self {
+                    account_infos,
+                    _borrow: std::marker::PhantomData,
+                }
+            }
+
+            pub fn as_slice(&self) -> &[CAccountInfo] {
+                &self.account_infos
+            }
+
+            pub fn len(&self) -> usize {
+                self.account_infos.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.account_infos.is_empty()
+            }
+
+            /// The address to pass as `account_infos_addr` to
+            /// `sol_invoke_signed_c`, which writes the callee's view of every
+            /// account back through it.
+            pub fn as_mut_ptr(&mut self) -> *mut u8 {
+                self.account_infos.as_mut_ptr() as *mut u8
+            }
         }
     };
 }
@@ -0,0 +1,1030 @@
+//! The C ABI shared by programs and loaders: the `#[repr(C)]` structs the
+//! syscalls pass, the [`SyscallStubsApi`] table and the constants both sides
+//! agree on.
+//!
+//! `common_stub_types!` re-exports this module, so a program and its loader
+//! built against the same release of this crate use the very same types. The
+//! assertions at the bottom pin their layout on 64-bit targets.
+
+pub const PUBKEY_BYTES: usize = 32;
+pub const HASH_BYTES: usize = 32;
+/// How much an account's data may grow within one instruction, and so
+/// the spare room the runtime serializes after every account's data.
+pub const MAX_PERMITTED_DATA_INCREASE: usize = 1_024 * 10;
+/// Largest account data length the runtime allows.
+pub const MAX_PERMITTED_DATA_LENGTH: u64 = 10 * 1024 * 1024;
+/// Largest return data a program may set, as the runtime enforces it.
+pub const MAX_RETURN_DATA: usize = 1_024;
+/// Generic failure code returned by syscalls that report errors as `1`.
+pub const SYSCALL_FAILURE: u64 = 1;
+/// Returned by an entrypoint run through `catch_program_abort` when the
+/// program was stopped. The loader knows why.
+pub const ENTRYPOINT_ABORTED: u64 = u64::MAX;
+
+/// Codes of the builtin `ProgramError`s, as the runtime returns them.
+/// Custom errors keep the upper 32 bits clear, so the two never collide.
+pub const CUSTOM_ZERO: u64 = 1 << 32;
+pub const INVALID_ARGUMENT: u64 = 2 << 32;
+pub const INVALID_INSTRUCTION_DATA: u64 = 3 << 32;
+pub const INVALID_ACCOUNT_DATA: u64 = 4 << 32;
+pub const ACCOUNT_DATA_TOO_SMALL: u64 = 5 << 32;
+pub const INSUFFICIENT_FUNDS: u64 = 6 << 32;
+pub const INCORRECT_PROGRAM_ID: u64 = 7 << 32;
+pub const MISSING_REQUIRED_SIGNATURES: u64 = 8 << 32;
+pub const ACCOUNT_ALREADY_INITIALIZED: u64 = 9 << 32;
+pub const UNINITIALIZED_ACCOUNT: u64 = 10 << 32;
+pub const NOT_ENOUGH_ACCOUNT_KEYS: u64 = 11 << 32;
+pub const ACCOUNT_BORROW_FAILED: u64 = 12 << 32;
+pub const MAX_SEED_LENGTH_EXCEEDED: u64 = 13 << 32;
+pub const INVALID_SEEDS: u64 = 14 << 32;
+pub const BORSH_IO_ERROR: u64 = 15 << 32;
+pub const ACCOUNT_NOT_RENT_EXEMPT: u64 = 16 << 32;
+/// Also returned by the `sol_get_*_sysvar` syscalls when the sysvar isn't available.
+pub const UNSUPPORTED_SYSVAR: u64 = 17 << 32;
+pub const ILLEGAL_OWNER: u64 = 18 << 32;
+pub const MAX_ACCOUNTS_DATA_ALLOCATIONS_EXCEEDED: u64 = 19 << 32;
+pub const INVALID_ACCOUNT_DATA_REALLOC: u64 = 20 << 32;
+pub const MAX_INSTRUCTION_TRACE_LENGTH_EXCEEDED: u64 = 21 << 32;
+pub const BUILTIN_PROGRAMS_MUST_CONSUME_COMPUTE_UNITS: u64 = 22 << 32;
+pub const INVALID_ACCOUNT_OWNER: u64 = 23 << 32;
+pub const ARITHMETIC_OVERFLOW: u64 = 24 << 32;
+pub const IMMUTABLE: u64 = 25 << 32;
+pub const INCORRECT_AUTHORITY: u64 = 26 << 32;
+
+/// `sol_get_sysvar` error codes.
+pub const OFFSET_LENGTH_EXCEEDS_SYSVAR: u64 = 1;
+pub const SYSVAR_NOT_FOUND: u64 = 2;
+
+/// Sysvar account addresses.
+pub const CLOCK_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 24, 199, 116, 201, 40, 86, 99, 152, 105, 29, 94, 182, 139, 94, 184, 163, 155,
+    75, 109, 92, 115, 85, 91, 33, 0, 0, 0, 0,
+];
+pub const EPOCH_SCHEDULE_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 24, 220, 63, 238, 2, 211, 228, 127, 1, 0, 248, 176, 84, 247, 148, 46, 96, 89,
+    30, 63, 80, 135, 25, 168, 5, 0, 0, 0,
+];
+pub const FEES_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 24, 226, 90, 141, 131, 80, 60, 37, 26, 122, 240, 113, 38, 253, 114, 0, 223,
+    111, 196, 237, 82, 106, 156, 144, 0, 0, 0, 0,
+];
+pub const RENT_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 25, 44, 92, 81, 33, 140, 201, 76, 61, 74, 241, 127, 88, 218, 238, 8, 155, 161,
+    253, 68, 227, 219, 217, 138, 0, 0, 0, 0,
+];
+pub const EPOCH_REWARDS_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 24, 220, 63, 238, 2, 165, 88, 191, 131, 206, 102, 225, 68, 66, 42, 28, 52,
+    149, 11, 39, 193, 134, 155, 90, 156, 0, 0, 0,
+];
+pub const LAST_RESTART_SLOT_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 25, 6, 221, 225, 205, 63, 148, 125, 202, 180, 200, 244, 244, 245, 27, 173, 15,
+    152, 19, 184, 0, 210, 137, 71, 31, 192, 0, 0,
+];
+pub const SLOT_HASHES_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 25, 47, 10, 175, 198, 242, 101, 227, 251, 119, 204, 122, 218, 130, 197, 41,
+    208, 190, 59, 19, 110, 45, 0, 85, 32, 0, 0, 0,
+];
+pub const STAKE_HISTORY_SYSVAR_ID: [u8; 32] = [
+    6, 167, 213, 23, 25, 53, 132, 208, 254, 237, 155, 179, 67, 29, 19, 32, 107, 229, 68, 40, 27,
+    87, 184, 86, 108, 197, 55, 95, 244, 0, 0, 0,
+];
+
+/// Sizes of the sysvar structs the `sol_get_*_sysvar` syscalls write.
+pub const CLOCK_SYSVAR_SIZE: usize = 40;
+pub const EPOCH_SCHEDULE_SYSVAR_SIZE: usize = 40;
+pub const FEES_SYSVAR_SIZE: usize = 8;
+pub const RENT_SYSVAR_SIZE: usize = 24;
+pub const EPOCH_REWARDS_SYSVAR_SIZE: usize = 96;
+pub const LAST_RESTART_SLOT_SYSVAR_SIZE: usize = 8;
+
+/// Most entries the SlotHashes and StakeHistory sysvars hold. Their
+/// accounts are sized for this many, zero-filled past the entries.
+pub const SLOT_HASHES_MAX_ENTRIES: usize = 512;
+pub const STAKE_HISTORY_MAX_ENTRIES: usize = 512;
+
+pub const SECP256K1_SIGNATURE_LENGTH: usize = 64;
+pub const SECP256K1_PUBLIC_KEY_LENGTH: usize = 64;
+/// `sol_secp256k1_recover` error codes, as encoded by `Secp256k1RecoverError`.
+pub const SECP256K1_RECOVER_ERROR_INVALID_HASH: u64 = 1;
+pub const SECP256K1_RECOVER_ERROR_INVALID_RECOVERY_ID: u64 = 2;
+pub const SECP256K1_RECOVER_ERROR_INVALID_SIGNATURE: u64 = 3;
+
+/// Curve ids accepted by the `sol_curve_*` syscalls.
+pub const CURVE25519_EDWARDS: u64 = 0;
+pub const CURVE25519_RISTRETTO: u64 = 1;
+/// Group operations accepted by `sol_curve_group_op`.
+pub const CURVE25519_ADD: u64 = 0;
+pub const CURVE25519_SUB: u64 = 1;
+pub const CURVE25519_MUL: u64 = 2;
+pub const CURVE25519_POINT_BYTES: usize = 32;
+pub const CURVE25519_SCALAR_BYTES: usize = 32;
+/// Largest `points_len` the runtime accepts in `sol_curve_multiscalar_mul`.
+pub const CURVE25519_MAX_MULTISCALAR_POINTS: u64 = 512;
+
+/// Group operations accepted by `sol_alt_bn128_group_op`.
+pub const ALT_BN128_ADD: u64 = 0;
+pub const ALT_BN128_SUB: u64 = 1;
+pub const ALT_BN128_MUL: u64 = 2;
+pub const ALT_BN128_PAIRING: u64 = 3;
+/// Operations accepted by `sol_alt_bn128_compression`.
+pub const ALT_BN128_G1_COMPRESS: u64 = 0;
+pub const ALT_BN128_G1_DECOMPRESS: u64 = 1;
+pub const ALT_BN128_G2_COMPRESS: u64 = 2;
+pub const ALT_BN128_G2_DECOMPRESS: u64 = 3;
+
+/// Size of the result written by `sol_alt_bn128_group_op`, `None` for unknown operations.
+pub const fn alt_bn128_group_op_result_len(group_op: u64) -> Option<usize> {
+    match group_op {
+        ALT_BN128_ADD | ALT_BN128_SUB | ALT_BN128_MUL => Some(64),
+        ALT_BN128_PAIRING => Some(32),
+        _ => None,
+    }
+}
+
+/// Size of the result written by `sol_alt_bn128_compression`, `None` for unknown operations.
+pub const fn alt_bn128_compression_result_len(op: u64) -> Option<usize> {
+    match op {
+        ALT_BN128_G1_COMPRESS => Some(32),
+        ALT_BN128_G1_DECOMPRESS | ALT_BN128_G2_COMPRESS => Some(64),
+        ALT_BN128_G2_DECOMPRESS => Some(128),
+        _ => None,
+    }
+}
+
+/// Parameter sets accepted by `sol_poseidon`.
+pub const POSEIDON_PARAMETERS_BN254_X5: u64 = 0;
+pub const POSEIDON_ENDIANNESS_BIG_ENDIAN: u64 = 0;
+pub const POSEIDON_ENDIANNESS_LITTLE_ENDIAN: u64 = 1;
+pub const POSEIDON_HASH_BYTES: usize = 32;
+/// Largest number of inputs the runtime hashes in one `sol_poseidon` call.
+pub const POSEIDON_MAX_INPUTS: u64 = 12;
+
+/// Largest base, exponent and modulus length accepted by `sol_big_mod_exp`.
+pub const BIG_MOD_EXP_MAX_INPUT_LEN: u64 = 512;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct CBigModExpParams {
+    pub base: *const u8,
+    pub base_len: u64,
+    pub exponent: *const u8,
+    pub exponent_len: u64,
+    pub modulus: *const u8,
+    pub modulus_len: u64,
+}
+
+#[repr(C)]
+pub struct CPubkey(pub [u8; PUBKEY_BYTES]);
+
+impl CPubkey {
+    pub const fn as_array(&self) -> &[u8; PUBKEY_BYTES] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for CPubkey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl AsMut<[u8]> for CPubkey {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0[..]
+    }
+}
+
+impl From<[u8; 32]> for CPubkey {
+    #[inline]
+    fn from(from: [u8; 32]) -> Self {
+        Self(from)
+    }
+}
+
+impl From<&[u8; 32]> for CPubkey {
+    #[inline]
+    fn from(from: &[u8; 32]) -> Self {
+        Self(*from)
+    }
+}
+
+impl PartialEq<[u8; 32]> for CPubkey {
+    fn eq(&self, other: &[u8; 32]) -> bool {
+        &self.0 == other
+    }
+}
+
+#[repr(C)]
+pub struct CProcessedSiblingInstruction {
+    pub data_len: u64,
+    pub accounts_len: u64,
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct CAccountMeta {
+    pub pubkey: *const CPubkey,
+    pub is_writable: bool,
+    pub is_signer: bool,
+}
+
+/// The key a default [`CAccountMeta`] points to.
+static ZERO_PUBKEY: CPubkey = CPubkey([0u8; PUBKEY_BYTES]);
+
+impl Default for CAccountMeta {
+    fn default() -> Self {
+        Self {
+            pubkey: &ZERO_PUBKEY,
+            is_writable: false,
+            is_signer: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct CAccountInfo {
+    // Public key of the account.
+    pub key: *const CPubkey,
+
+    // Number of lamports owned by this account.
+    pub lamports: *const u64,
+
+    // Length of data in bytes.
+    pub data_len: u64,
+
+    // On-chain data within this account.
+    pub data: *const u8,
+
+    // Program that owns this account.
+    pub owner: *const CPubkey,
+
+    // The epoch at which this account will next owe rent.
+    pub rent_epoch: u64,
+
+    // Transaction was signed by this account's key?
+    pub is_signer: bool,
+
+    // Is the account writable?
+    pub is_writable: bool,
+
+    // This account's data contains a loaded program (and is now read-only).
+    pub executable: bool,
+
+    // Length of data when the account was serialized for the current instruction.
+    pub original_data_len: u64,
+
+    // Number of bytes at `data` that may be written to, i.e. `original_data_len`
+    // plus the `MAX_PERMITTED_DATA_INCREASE` padding, capped by
+    // `MAX_PERMITTED_DATA_LENGTH`.
+    pub data_capacity: u64,
+}
+
+/// The account layout the runtime's own `sol_invoke_signed_c` takes, as
+/// pinocchio and the C SDK pass it. [`CAccountInfo`] extends it with the
+/// lengths needed to bound a resize.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SolAccountInfo {
+    pub key: *const CPubkey,
+    pub lamports: *const u64,
+    pub data_len: u64,
+    pub data: *const u8,
+    pub owner: *const CPubkey,
+    pub rent_epoch: u64,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub executable: bool,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct CInstruction {
+    /// Public key of the program.
+    pub program_id: *const CPubkey,
+
+    /// Accounts expected by the program instruction.
+    pub accounts: *const CAccountMeta,
+
+    /// Number of accounts expected by the program instruction.
+    pub accounts_len: u64,
+
+    /// Data expected by the program instruction.
+    pub data: *const u8,
+
+    /// Length of the data expected by the program instruction.
+    pub data_len: u64,
+}
+
+/// Most account metas a CPI instruction may carry.
+pub const MAX_CPI_INSTRUCTION_ACCOUNTS: usize = u8::MAX as usize;
+/// Most data bytes a CPI instruction may carry.
+pub const MAX_CPI_INSTRUCTION_DATA_LEN: usize = 10 * 1024;
+
+/// Magic value ("SOLSTUBS") opening every [`SyscallStubsApi`] table.
+pub const SYSCALL_STUBS_API_MAGIC: u64 = u64::from_le_bytes(*b"SOLSTUBS");
+/// Bumped on incompatible changes to the layout of the table or of the types it passes.
+pub const SYSCALL_STUBS_API_VERSION_MAJOR: u32 = 2;
+/// Bumped when entries are appended to the end of the table.
+pub const SYSCALL_STUBS_API_VERSION_MINOR: u32 = 2;
+
+/// Capability bits advertised by the loader in [`SyscallStubsApiHeader::capabilities`].
+pub const SYSCALL_STUBS_CAP_LOG: u64 = 1 << 0;
+pub const SYSCALL_STUBS_CAP_COMPUTE_UNITS: u64 = 1 << 1;
+pub const SYSCALL_STUBS_CAP_CPI: u64 = 1 << 2;
+pub const SYSCALL_STUBS_CAP_SYSVARS: u64 = 1 << 3;
+pub const SYSCALL_STUBS_CAP_MEMOPS: u64 = 1 << 4;
+pub const SYSCALL_STUBS_CAP_RETURN_DATA: u64 = 1 << 5;
+pub const SYSCALL_STUBS_CAP_SIBLING_INSTRUCTIONS: u64 = 1 << 6;
+pub const SYSCALL_STUBS_CAP_STACK_HEIGHT: u64 = 1 << 7;
+pub const SYSCALL_STUBS_CAP_EPOCH_STAKE: u64 = 1 << 8;
+pub const SYSCALL_STUBS_CAP_HASHING: u64 = 1 << 9;
+pub const SYSCALL_STUBS_CAP_PDA: u64 = 1 << 10;
+pub const SYSCALL_STUBS_CAP_SECP256K1: u64 = 1 << 11;
+pub const SYSCALL_STUBS_CAP_CURVE25519: u64 = 1 << 12;
+pub const SYSCALL_STUBS_CAP_ALT_BN128: u64 = 1 << 13;
+pub const SYSCALL_STUBS_CAP_POSEIDON: u64 = 1 << 14;
+pub const SYSCALL_STUBS_CAP_BIG_MOD_EXP: u64 = 1 << 15;
+/// The loader accepts panic reports through `sol_panic_`.
+pub const SYSCALL_STUBS_CAP_PANIC: u64 = 1 << 16;
+/// All capabilities known to this version of the crate.
+pub const SYSCALL_STUBS_CAPS_ALL: u64 = SYSCALL_STUBS_CAP_LOG
+    | SYSCALL_STUBS_CAP_COMPUTE_UNITS
+    | SYSCALL_STUBS_CAP_CPI
+    | SYSCALL_STUBS_CAP_SYSVARS
+    | SYSCALL_STUBS_CAP_MEMOPS
+    | SYSCALL_STUBS_CAP_RETURN_DATA
+    | SYSCALL_STUBS_CAP_SIBLING_INSTRUCTIONS
+    | SYSCALL_STUBS_CAP_STACK_HEIGHT
+    | SYSCALL_STUBS_CAP_EPOCH_STAKE
+    | SYSCALL_STUBS_CAP_HASHING
+    | SYSCALL_STUBS_CAP_PDA
+    | SYSCALL_STUBS_CAP_SECP256K1
+    | SYSCALL_STUBS_CAP_CURVE25519
+    | SYSCALL_STUBS_CAP_ALT_BN128
+    | SYSCALL_STUBS_CAP_POSEIDON
+    | SYSCALL_STUBS_CAP_BIG_MOD_EXP
+    | SYSCALL_STUBS_CAP_PANIC;
+
+/// Versioned header at the start of [`SyscallStubsApi`].
+///
+/// Its layout never changes, so the receiving side can always read it
+/// before deciding whether the rest of the table is safe to use.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallStubsApiHeader {
+    pub magic: u64,
+    pub version_major: u32,
+    pub version_minor: u32,
+    /// `size_of::<SyscallStubsApi>()` on the side that built the table.
+    pub struct_size: u64,
+    pub capabilities: u64,
+}
+
+impl SyscallStubsApiHeader {
+    /// A header describing the table layout of this build.
+    pub const fn current(capabilities: u64) -> Self {
+        Self {
+            magic: SYSCALL_STUBS_API_MAGIC,
+            version_major: SYSCALL_STUBS_API_VERSION_MAJOR,
+            version_minor: SYSCALL_STUBS_API_VERSION_MINOR,
+            struct_size: std::mem::size_of::<SyscallStubsApi>() as u64,
+            capabilities,
+        }
+    }
+
+    /// Checks whether a table carrying this header can be read as this
+    /// build's [`SyscallStubsApi`].
+    ///
+    /// A table from a newer minor version is accepted: only the prefix
+    /// known to this build is used.
+    pub fn negotiate(&self) -> Result<(), SetStubsError> {
+        if self.magic != SYSCALL_STUBS_API_MAGIC {
+            return Err(SetStubsError::BadMagic);
+        }
+        if self.version_major != SYSCALL_STUBS_API_VERSION_MAJOR
+            || self.version_minor < SYSCALL_STUBS_API_VERSION_MINOR
+        {
+            return Err(SetStubsError::IncompatibleVersion {
+                loader: (self.version_major, self.version_minor),
+                program: (
+                    SYSCALL_STUBS_API_VERSION_MAJOR,
+                    SYSCALL_STUBS_API_VERSION_MINOR,
+                ),
+            });
+        }
+        let program_size = std::mem::size_of::<SyscallStubsApi>() as u64;
+        if self.struct_size < program_size {
+            return Err(SetStubsError::TableTooSmall {
+                loader: self.struct_size,
+                program: program_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Returned by `set_stubs` when the table was accepted.
+pub const SET_STUBS_SUCCESS: u64 = 0;
+/// Returned by `set_stubs` for each [`SetStubsError`].
+pub const SET_STUBS_NULL_TABLE: u64 = 1;
+pub const SET_STUBS_BAD_MAGIC: u64 = 2;
+pub const SET_STUBS_INCOMPATIBLE_VERSION: u64 = 3;
+pub const SET_STUBS_TABLE_TOO_SMALL: u64 = 4;
+pub const SET_STUBS_LAYOUT_MISMATCH: u64 = 5;
+
+/// Reasons for `set_stubs` to reject a [`SyscallStubsApi`] table, with what
+/// each side had where they disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetStubsError {
+    NullTable,
+    BadMagic,
+    /// ABI versions as `(major, minor)`.
+    IncompatibleVersion {
+        loader: (u32, u32),
+        program: (u32, u32),
+    },
+    /// Table sizes in bytes.
+    TableTooSmall {
+        loader: u64,
+        program: u64,
+    },
+    /// Layout fingerprints.
+    LayoutMismatch {
+        loader: u64,
+        program: u64,
+    },
+}
+
+impl SetStubsError {
+    /// The code `set_stubs` returns for this error.
+    pub const fn code(&self) -> u64 {
+        match self {
+            Self::NullTable => SET_STUBS_NULL_TABLE,
+            Self::BadMagic => SET_STUBS_BAD_MAGIC,
+            Self::IncompatibleVersion { .. } => SET_STUBS_INCOMPATIBLE_VERSION,
+            Self::TableTooSmall { .. } => SET_STUBS_TABLE_TOO_SMALL,
+            Self::LayoutMismatch { .. } => SET_STUBS_LAYOUT_MISMATCH,
+        }
+    }
+
+    /// Why `set_stubs` returned `code`, for loaders, which only see the code.
+    /// `None` for codes no error has.
+    pub const fn reason(code: u64) -> Option<&'static str> {
+        match code {
+            SET_STUBS_NULL_TABLE => Some("the syscall stubs table is null"),
+            SET_STUBS_BAD_MAGIC => Some("the syscall stubs table has no valid header"),
+            SET_STUBS_INCOMPATIBLE_VERSION => {
+                Some("the loader and the program use incompatible syscall stubs ABI versions")
+            }
+            SET_STUBS_TABLE_TOO_SMALL => {
+                Some("the loader's syscall stubs table is smaller than the program expects")
+            }
+            SET_STUBS_LAYOUT_MISMATCH => {
+                Some("the loader and the program were built with different C ABI layouts")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SetStubsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(Self::reason(self.code()).unwrap_or_default())?;
+        match self {
+            Self::NullTable | Self::BadMagic => Ok(()),
+            Self::IncompatibleVersion { loader, program } => write!(
+                f,
+                " (loader: ABI {}.{}; program: ABI {}.{})",
+                loader.0, loader.1, program.0, program.1
+            ),
+            Self::TableTooSmall { loader, program } => {
+                write!(f, " (loader: {loader} bytes; program: {program} bytes)")
+            }
+            Self::LayoutMismatch { loader, program } => write!(
+                f,
+                " (loader: fingerprint {loader:#018x}; program: fingerprint {program:#018x})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SetStubsError {}
+
+impl SyscallStubsApi {
+    /// Reads the table a loader passed to `set_stubs`, after checking its
+    /// header and layout fingerprint.
+    ///
+    /// # Safety
+    ///
+    /// `stubs_api`, if not null, must point to a table at least as large as
+    /// its header's `struct_size`.
+    pub unsafe fn accept(stubs_api: *const SyscallStubsApi) -> Result<Self, SetStubsError> {
+        if stubs_api.is_null() {
+            return Err(SetStubsError::NullTable);
+        }
+        // The header layout is fixed across versions, so it is safe to read
+        // before anything else is known about the table.
+        let header = std::ptr::read(stubs_api as *const SyscallStubsApiHeader);
+        header.negotiate()?;
+        // Negotiation guarantees the loader's table is at least as large as ours.
+        let stubs_api = std::ptr::read(stubs_api);
+        // The fingerprint covers only what every minor version shares, so a
+        // newer loader's is compared too.
+        if stubs_api.layout_fingerprint != SYSCALL_STUBS_ABI_FINGERPRINT {
+            return Err(SetStubsError::LayoutMismatch {
+                loader: stubs_api.layout_fingerprint,
+                program: SYSCALL_STUBS_ABI_FINGERPRINT,
+            });
+        }
+        Ok(stubs_api)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SyscallStubsApi {
+    pub header: SyscallStubsApiHeader,
+    pub sol_log_: extern "C" fn(message: *const u8, len: u64),
+    pub sol_log_compute_units_: extern "C" fn(),
+    pub sol_remaining_compute_units: extern "C" fn() -> u64,
+    pub sol_invoke_signed_c: extern "C" fn(
+        instruction_addr: *const u8,
+        account_infos_addr: *const u8,
+        account_infos_len: u64,
+        signers_seeds_addr: *const u8,
+        signers_seeds_len: u64,
+    ) -> u64,
+    pub sol_get_clock_sysvar: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_epoch_schedule_sysvar: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_fees_sysvar: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_rent_sysvar: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_last_restart_slot: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_sysvar:
+        extern "C" fn(sysvar_id_addr: *const u8, result: *mut u8, offset: u64, length: u64) -> u64,
+    pub sol_memcpy_: extern "C" fn(dst: *mut u8, src: *const u8, n: u64),
+    pub sol_memmove_: extern "C" fn(dst: *mut u8, src: *const u8, n: u64),
+    pub sol_memcmp_: extern "C" fn(s1: *const u8, s2: *const u8, n: u64, result: *mut i32),
+    pub sol_memset_: extern "C" fn(s: *mut u8, c: u8, n: u64),
+    pub sol_get_return_data:
+        extern "C" fn(data: *mut u8, length: u64, program_id: *mut CPubkey) -> u64,
+    pub sol_set_return_data: extern "C" fn(data: *const u8, length: u64),
+    pub sol_log_data: extern "C" fn(data: *const u8, data_len: u64),
+    pub sol_get_processed_sibling_instruction: extern "C" fn(
+        index: u64,
+        meta: *mut CProcessedSiblingInstruction,
+        program_id: *mut CPubkey,
+        data: *mut u8,
+        accounts: *mut CAccountMeta,
+    ) -> u64,
+    pub sol_get_stack_height: extern "C" fn() -> u64,
+    pub sol_get_epoch_rewards_sysvar: extern "C" fn(addr: *mut u8) -> u64,
+    pub sol_get_epoch_stake: extern "C" fn(vote_address: *const u8) -> u64,
+    // `vals` points to `val_len` (ptr, len) slice descriptors.
+    pub sol_sha256: extern "C" fn(vals: *const u8, val_len: u64, hash_result: *mut u8) -> u64,
+    pub sol_keccak256: extern "C" fn(vals: *const u8, val_len: u64, hash_result: *mut u8) -> u64,
+    pub sol_blake3: extern "C" fn(vals: *const u8, val_len: u64, hash_result: *mut u8) -> u64,
+    // `seeds_addr` points to `seeds_len` (ptr, len) slice descriptors.
+    pub sol_create_program_address: extern "C" fn(
+        seeds_addr: *const u8,
+        seeds_len: u64,
+        program_id_addr: *const u8,
+        address_bytes_addr: *mut u8,
+    ) -> u64,
+    pub sol_try_find_program_address: extern "C" fn(
+        seeds_addr: *const u8,
+        seeds_len: u64,
+        program_id_addr: *const u8,
+        address_bytes_addr: *mut u8,
+        bump_seed_addr: *mut u8,
+    ) -> u64,
+    pub sol_secp256k1_recover: extern "C" fn(
+        hash: *const u8,
+        recovery_id: u64,
+        signature: *const u8,
+        result: *mut u8,
+    ) -> u64,
+    pub sol_curve_validate_point:
+        extern "C" fn(curve_id: u64, point_addr: *const u8, result: *mut u8) -> u64,
+    pub sol_curve_group_op: extern "C" fn(
+        curve_id: u64,
+        group_op: u64,
+        left_input_addr: *const u8,
+        right_input_addr: *const u8,
+        result_point_addr: *mut u8,
+    ) -> u64,
+    pub sol_curve_multiscalar_mul: extern "C" fn(
+        curve_id: u64,
+        scalars_addr: *const u8,
+        points_addr: *const u8,
+        points_len: u64,
+        result_point_addr: *mut u8,
+    ) -> u64,
+    pub sol_alt_bn128_group_op:
+        extern "C" fn(group_op: u64, input: *const u8, input_size: u64, result: *mut u8) -> u64,
+    pub sol_alt_bn128_compression:
+        extern "C" fn(op: u64, input: *const u8, input_size: u64, result: *mut u8) -> u64,
+    pub sol_poseidon: extern "C" fn(
+        parameters: u64,
+        endianness: u64,
+        vals: *const u8,
+        val_len: u64,
+        hash_result: *mut u8,
+    ) -> u64,
+    pub sol_big_mod_exp: extern "C" fn(params: *const u8, result: *mut u8) -> u64,
+    pub sol_log_64_: extern "C" fn(arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64),
+    pub sol_log_pubkey: extern "C" fn(pubkey_addr: *const u8),
+    pub sol_panic_: extern "C" fn(
+        file: *const u8,
+        file_len: u64,
+        line: u64,
+        column: u64,
+        message: *const u8,
+        message_len: u64,
+    ),
+    /// [`SYSCALL_STUBS_ABI_FINGERPRINT`] of the side that built the table.
+    /// Added in ABI 2.1; entries appended later go after it.
+    pub layout_fingerprint: u64,
+    /// Not a runtime syscall: returns 1 once the loader has aborted the
+    /// program running on the calling thread, which must then stop. Added in
+    /// ABI 2.2.
+    pub program_aborted: extern "C" fn() -> u64,
+}
+
+/// Number of function pointers in [`SyscallStubsApi`]: every field past the
+/// header but the fingerprint.
+pub const SYSCALL_STUBS_API_FN_COUNT: usize = (std::mem::size_of::<SyscallStubsApi>()
+    - std::mem::size_of::<SyscallStubsApiHeader>()
+    - std::mem::size_of::<u64>())
+    / std::mem::size_of::<extern "C" fn()>();
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Folds `value` into an FNV-1a hash.
+const fn fnv1a(mut hash: u64, value: u64) -> u64 {
+    let bytes = value.to_le_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Folds the size and alignment of `$ty`, and the offsets of `$field`s, into `$hash`.
+macro_rules! fold_layout {
+    ($hash:expr, $ty:ty $(, $field:tt)* $(,)?) => {{
+        let mut hash = fnv1a($hash, std::mem::size_of::<$ty>() as u64);
+        hash = fnv1a(hash, std::mem::align_of::<$ty>() as u64);
+        $(hash = fnv1a(hash, std::mem::offset_of!($ty, $field) as u64);)*
+        hash
+    }};
+}
+
+/// Number of function pointers in [`SyscallStubsApi`] before the fingerprint:
+/// the part of the table every minor version of this major version shares.
+const SYSCALL_STUBS_API_PREFIX_FN_COUNT: usize =
+    (std::mem::offset_of!(SyscallStubsApi, layout_fingerprint)
+        - std::mem::size_of::<SyscallStubsApiHeader>())
+        / std::mem::size_of::<extern "C" fn()>();
+
+/// Hash of the layout of every type the two sides exchange, and of the
+/// table entries up to and including the fingerprint.
+///
+/// It is computed for the target being built, so a program and a loader that
+/// agree on ABI versions but were built for different targets, or with a
+/// different crate release, still tell each other apart at `set_stubs` time.
+/// Entries appended in later minor versions are left out, so both sides of
+/// any minor versions they negotiate compute the same value.
+pub const SYSCALL_STUBS_ABI_FINGERPRINT: u64 = {
+    let mut hash = FNV_OFFSET_BASIS;
+    hash = fold_layout!(hash, CPubkey, 0);
+    hash = fold_layout!(
+        hash,
+        CBigModExpParams,
+        base,
+        base_len,
+        exponent,
+        exponent_len,
+        modulus,
+        modulus_len,
+    );
+    hash = fold_layout!(hash, CProcessedSiblingInstruction, data_len, accounts_len);
+    hash = fold_layout!(hash, CAccountMeta, pubkey, is_writable, is_signer);
+    hash = fold_layout!(
+        hash,
+        CAccountInfo,
+        key,
+        lamports,
+        data_len,
+        data,
+        owner,
+        rent_epoch,
+        is_signer,
+        is_writable,
+        executable,
+        original_data_len,
+        data_capacity,
+    );
+    hash = fold_layout!(
+        hash,
+        CInstruction,
+        program_id,
+        accounts,
+        accounts_len,
+        data,
+        data_len,
+    );
+    hash = fold_layout!(
+        hash,
+        SyscallStubsApiHeader,
+        magic,
+        version_major,
+        version_minor,
+        struct_size,
+        capabilities,
+    );
+    hash = fnv1a(hash, std::mem::offset_of!(SyscallStubsApi, header) as u64);
+    hash = fnv1a(
+        hash,
+        std::mem::offset_of!(SyscallStubsApi, layout_fingerprint) as u64,
+    );
+    fnv1a(hash, SYSCALL_STUBS_API_PREFIX_FN_COUNT as u64)
+};
+
+/// Checks at compile time that `$ty` is `$size` bytes, aligned to `$align`,
+/// with each listed field at its offset.
+macro_rules! assert_layout {
+    ($ty:ty, size = $size:expr, align = $align:expr $(, $field:ident = $offset:expr)* $(,)?) => {
+        const _: () = {
+            assert!(std::mem::size_of::<$ty>() == $size);
+            assert!(std::mem::align_of::<$ty>() == $align);
+            $(assert!(std::mem::offset_of!($ty, $field) == $offset);)*
+        };
+    };
+}
+
+assert_layout!(CPubkey, size = 32, align = 1);
+
+#[cfg(target_pointer_width = "64")]
+mod layout {
+    use super::*;
+
+    assert_layout!(
+        CBigModExpParams,
+        size = 48,
+        align = 8,
+        base = 0,
+        base_len = 8,
+        exponent = 16,
+        exponent_len = 24,
+        modulus = 32,
+        modulus_len = 40,
+    );
+    assert_layout!(
+        CProcessedSiblingInstruction,
+        size = 16,
+        align = 8,
+        data_len = 0,
+        accounts_len = 8,
+    );
+    assert_layout!(
+        CAccountMeta,
+        size = 16,
+        align = 8,
+        pubkey = 0,
+        is_writable = 8,
+        is_signer = 9,
+    );
+    assert_layout!(
+        CAccountInfo,
+        size = 72,
+        align = 8,
+        key = 0,
+        lamports = 8,
+        data_len = 16,
+        data = 24,
+        owner = 32,
+        rent_epoch = 40,
+        is_signer = 48,
+        is_writable = 49,
+        executable = 50,
+        original_data_len = 56,
+        data_capacity = 64,
+    );
+    assert_layout!(
+        SolAccountInfo,
+        size = 56,
+        align = 8,
+        key = 0,
+        lamports = 8,
+        data_len = 16,
+        data = 24,
+        owner = 32,
+        rent_epoch = 40,
+        is_signer = 48,
+        is_writable = 49,
+        executable = 50,
+    );
+    assert_layout!(
+        CInstruction,
+        size = 40,
+        align = 8,
+        program_id = 0,
+        accounts = 8,
+        accounts_len = 16,
+        data = 24,
+        data_len = 32,
+    );
+    assert_layout!(
+        SyscallStubsApiHeader,
+        size = 32,
+        align = 8,
+        magic = 0,
+        version_major = 8,
+        version_minor = 12,
+        struct_size = 16,
+        capabilities = 24,
+    );
+    // The header, then one function pointer per syscall in declaration order,
+    // then the fingerprint, then the entries appended since.
+    assert_layout!(
+        SyscallStubsApi,
+        size = 32 + 37 * 8 + 8 + 8,
+        align = 8,
+        header = 0,
+        sol_log_ = 32,
+        sol_panic_ = 32 + 36 * 8,
+        layout_fingerprint = 32 + 37 * 8,
+        program_aborted = 32 + 37 * 8 + 8,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table with the given header and fingerprint whose syscalls are never called.
+    fn table(header: SyscallStubsApiHeader, layout_fingerprint: u64) -> SyscallStubsApi {
+        extern "C" fn unused() {}
+        const WORDS: usize =
+            std::mem::size_of::<SyscallStubsApi>() / std::mem::size_of::<*const ()>();
+        // Every entry is a function pointer or is overwritten below.
+        let mut table = unsafe {
+            std::mem::transmute::<[*const (); WORDS], SyscallStubsApi>([unused as *const (); WORDS])
+        };
+        table.header = header;
+        table.layout_fingerprint = layout_fingerprint;
+        table
+    }
+
+    fn current() -> SyscallStubsApiHeader {
+        SyscallStubsApiHeader::current(SYSCALL_STUBS_CAPS_ALL)
+    }
+
+    #[test]
+    fn current_header_describes_this_build() {
+        let header = current();
+        assert_eq!(header.magic.to_le_bytes(), *b"SOLSTUBS");
+        assert_eq!(
+            (header.version_major, header.version_minor),
+            (
+                SYSCALL_STUBS_API_VERSION_MAJOR,
+                SYSCALL_STUBS_API_VERSION_MINOR
+            )
+        );
+        assert_eq!(
+            header.struct_size,
+            std::mem::size_of::<SyscallStubsApi>() as u64
+        );
+        assert_eq!(header.capabilities, SYSCALL_STUBS_CAPS_ALL);
+        assert_eq!(SYSCALL_STUBS_CAPS_ALL, (SYSCALL_STUBS_CAP_PANIC << 1) - 1);
+        assert_eq!(header.negotiate(), Ok(()));
+    }
+
+    #[test]
+    fn default_account_meta_points_to_the_zero_key() {
+        let meta = CAccountMeta::default();
+        assert_eq!(unsafe { &*meta.pubkey }.0, [0; PUBKEY_BYTES]);
+        assert!(!meta.is_writable && !meta.is_signer);
+    }
+
+    #[test]
+    fn negotiate_checks_magic_version_and_size() {
+        let bad_magic = SyscallStubsApiHeader {
+            magic: 0,
+            ..current()
+        };
+        assert_eq!(bad_magic.negotiate(), Err(SetStubsError::BadMagic));
+        let other_major = SyscallStubsApiHeader {
+            version_major: SYSCALL_STUBS_API_VERSION_MAJOR + 1,
+            ..current()
+        };
+        assert_eq!(
+            other_major.negotiate(),
+            Err(SetStubsError::IncompatibleVersion {
+                loader: (
+                    SYSCALL_STUBS_API_VERSION_MAJOR + 1,
+                    SYSCALL_STUBS_API_VERSION_MINOR
+                ),
+                program: (
+                    SYSCALL_STUBS_API_VERSION_MAJOR,
+                    SYSCALL_STUBS_API_VERSION_MINOR
+                ),
+            })
+        );
+        let older_minor = SyscallStubsApiHeader {
+            version_minor: SYSCALL_STUBS_API_VERSION_MINOR - 1,
+            ..current()
+        };
+        assert!(matches!(
+            older_minor.negotiate(),
+            Err(SetStubsError::IncompatibleVersion { .. })
+        ));
+        let too_small = SyscallStubsApiHeader {
+            struct_size: current().struct_size - 8,
+            ..current()
+        };
+        assert_eq!(
+            too_small.negotiate(),
+            Err(SetStubsError::TableTooSmall {
+                loader: current().struct_size - 8,
+                program: current().struct_size,
+            })
+        );
+        let newer_minor = SyscallStubsApiHeader {
+            version_minor: SYSCALL_STUBS_API_VERSION_MINOR + 1,
+            struct_size: current().struct_size + 8,
+            ..current()
+        };
+        assert_eq!(newer_minor.negotiate(), Ok(()));
+    }
+
+    #[test]
+    fn accept_checks_the_header_and_fingerprint() {
+        assert_eq!(
+            unsafe { SyscallStubsApi::accept(std::ptr::null()) }.err(),
+            Some(SetStubsError::NullTable)
+        );
+        let accepted = table(current(), SYSCALL_STUBS_ABI_FINGERPRINT);
+        let copy = unsafe { SyscallStubsApi::accept(&accepted) }.unwrap();
+        assert_eq!(copy.header, accepted.header);
+
+        let bad_magic = table(
+            SyscallStubsApiHeader {
+                magic: 0,
+                ..current()
+            },
+            SYSCALL_STUBS_ABI_FINGERPRINT,
+        );
+        assert_eq!(
+            unsafe { SyscallStubsApi::accept(&bad_magic) }.err(),
+            Some(SetStubsError::BadMagic)
+        );
+        let mismatch = table(current(), !SYSCALL_STUBS_ABI_FINGERPRINT);
+        assert_eq!(
+            unsafe { SyscallStubsApi::accept(&mismatch) }.err(),
+            Some(SetStubsError::LayoutMismatch {
+                loader: !SYSCALL_STUBS_ABI_FINGERPRINT,
+                program: SYSCALL_STUBS_ABI_FINGERPRINT,
+            })
+        );
+        // A newer minor version's fingerprint is compared as well.
+        let newer_minor = |layout_fingerprint| {
+            table(
+                SyscallStubsApiHeader {
+                    version_minor: SYSCALL_STUBS_API_VERSION_MINOR + 1,
+                    ..current()
+                },
+                layout_fingerprint,
+            )
+        };
+        assert!(
+            unsafe { SyscallStubsApi::accept(&newer_minor(SYSCALL_STUBS_ABI_FINGERPRINT)) }.is_ok()
+        );
+        assert!(matches!(
+            unsafe { SyscallStubsApi::accept(&newer_minor(!SYSCALL_STUBS_ABI_FINGERPRINT)) },
+            Err(SetStubsError::LayoutMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn set_stubs_errors_explain_their_codes() {
+        let error = SetStubsError::TableTooSmall {
+            loader: 40,
+            program: 48,
+        };
+        assert_eq!(error.code(), SET_STUBS_TABLE_TOO_SMALL);
+        assert_eq!(
+            error.to_string(),
+            "the loader's syscall stubs table is smaller than the program expects \
+             (loader: 40 bytes; program: 48 bytes)"
+        );
+        for code in SET_STUBS_NULL_TABLE..=SET_STUBS_LAYOUT_MISMATCH {
+            assert!(SetStubsError::reason(code).is_some());
+        }
+        assert_eq!(SetStubsError::reason(SET_STUBS_SUCCESS), None);
+    }
+}
@@ -1,5 +1,9 @@
 /// This macro exports a global alternative container to ProgramTest's SYSCALL_STUBS
-/// necessary at loader.
+/// necessary at loader, along with per-program stub contexts that take precedence over it.
+///
+/// Since 0.2 the container holds a `Box<dyn LoaderSyscallStubs>` instead of a
+/// `Box<dyn SyscallStubs>`. Stubs written for 0.1 keep their behaviour with an
+/// empty `impl LoaderSyscallStubs for MyStubs {}`.
 #[macro_export]
 macro_rules! declare_sol_loader_stubs {
     () => {
@@ -8,246 +12,3967 @@ macro_rules! declare_sol_loader_stubs {
         pub use lazy_static;
 
         lazy_static::lazy_static! {
-            pub static ref SYSCALL_STUBS: Arc<RwLock<Box<dyn SyscallStubs>>> =
-                Arc::new(RwLock::new(Box::new(UnimplementedSyscallStubs {})));
+            pub static ref SYSCALL_STUBS: Arc<RwLock<Box<dyn LoaderSyscallStubs>>> =
+                Arc::new(RwLock::new(Box::new(NativeSyscallStubs::default())));
+            static ref STUB_CONTEXTS: RwLock<
+                std::collections::HashMap<StubContextId, Arc<dyn LoaderSyscallStubs>>,
+            > = RwLock::new(std::collections::HashMap::new());
+            static ref PROGRAM_STUB_CONTEXTS: RwLock<std::collections::HashMap<Pubkey, StubContextId>> =
+                RwLock::new(std::collections::HashMap::new());
         }
 
+        /// Handle of a stub context registered with [`register_stub_context`].
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct StubContextId(pub u64);
+
+        std::thread_local! {
+            /// Contexts entered on this thread, innermost last.
+            static CURRENT_STUB_CONTEXTS: std::cell::RefCell<
+                Vec<(StubContextId, Arc<dyn LoaderSyscallStubs>)>,
+            > = const { std::cell::RefCell::new(Vec::new()) };
+        }
+
+        /// Registers `stubs` as a context of its own and returns its handle.
+        pub fn register_stub_context(stubs: Box<dyn LoaderSyscallStubs>) -> StubContextId {
+            static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+            let id = StubContextId(NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+            STUB_CONTEXTS.write().unwrap().insert(id, Arc::from(stubs));
+            id
+        }
+
+        /// Registers `stubs` as the context of `program_id`, replacing any
+        /// context previously registered for it.
+        pub fn register_program_stub_context(
+            program_id: Pubkey,
+            stubs: Box<dyn LoaderSyscallStubs>,
+        ) -> StubContextId {
+            let id = register_stub_context(stubs);
+            if let Some(old) = PROGRAM_STUB_CONTEXTS.write().unwrap().insert(program_id, id) {
+                STUB_CONTEXTS.write().unwrap().remove(&old);
+            }
+            id
+        }
+
+        /// Removes a context. Threads that already entered it keep using it
+        /// until their guard is dropped.
+        pub fn unregister_stub_context(id: StubContextId) {
+            STUB_CONTEXTS.write().unwrap().remove(&id);
+            PROGRAM_STUB_CONTEXTS
+                .write()
+                .unwrap()
+                .retain(|_, context| *context != id);
+        }
+
+        /// Returns the context registered for `program_id`, if any.
+        pub fn program_stub_context(program_id: &Pubkey) -> Option<StubContextId> {
+            PROGRAM_STUB_CONTEXTS.read().unwrap().get(program_id).copied()
+        }
+
+        /// Returns the innermost context entered on this thread, if any.
+        pub fn current_stub_context() -> Option<StubContextId> {
+            CURRENT_STUB_CONTEXTS.with(|contexts| contexts.borrow().last().map(|(id, _)| *id))
+        }
+
+        /// Makes `id` the context syscalls on this thread dispatch to until the
+        /// returned guard is dropped. Contexts nest, so a loader handling a CPI
+        /// enters the callee's context and drops it when the callee returns.
+        ///
+        /// Returns `None` if `id` is not registered.
+        pub fn enter_stub_context(id: StubContextId) -> Option<StubContextGuard> {
+            let stubs = STUB_CONTEXTS.read().unwrap().get(&id)?.clone();
+            CURRENT_STUB_CONTEXTS.with(|contexts| contexts.borrow_mut().push((id, stubs)));
+            Some(StubContextGuard {
+                _not_send: std::marker::PhantomData,
+            })
+        }
+
+        /// Enters the context registered for `program_id`, if any.
+        pub fn enter_program_stub_context(program_id: &Pubkey) -> Option<StubContextGuard> {
+            enter_stub_context(program_stub_context(program_id)?)
+        }
+
+        /// Leaves the context entered by [`enter_stub_context`] when dropped.
+        #[must_use]
+        pub struct StubContextGuard {
+            _not_send: std::marker::PhantomData<*const ()>,
+        }
+
+        impl Drop for StubContextGuard {
+            fn drop(&mut self) {
+                CURRENT_STUB_CONTEXTS.with(|contexts| contexts.borrow_mut().pop());
+            }
+        }
+
+        /// Stubs the exported syscalls dispatch to: the innermost context
+        /// entered on this thread, or [`SYSCALL_STUBS`] when there is none.
+        ///
+        /// Dropping it releases the stubs first and only then applies an abort
+        /// they asked for with [`request_program_abort`].
+        pub struct CurrentSyscallStubs(Option<DispatchedStubs>);
+
+        enum DispatchedStubs {
+            Context(Arc<dyn LoaderSyscallStubs>),
+            Global(std::sync::RwLockReadGuard<'static, Box<dyn LoaderSyscallStubs>>),
+        }
+
+        impl std::ops::Deref for CurrentSyscallStubs {
+            type Target = dyn LoaderSyscallStubs;
+
+            fn deref(&self) -> &Self::Target {
+                match self.0.as_ref().unwrap() {
+                    DispatchedStubs::Context(stubs) => stubs.as_ref(),
+                    DispatchedStubs::Global(stubs) => stubs.as_ref(),
+                }
+            }
+        }
+
+        impl Drop for CurrentSyscallStubs {
+            fn drop(&mut self) {
+                drop(self.0.take());
+                if let Some(abort) = REQUESTED_ABORT.with(|requested| requested.take()) {
+                    abort_program(abort);
+                }
+            }
+        }
+
+        /// Resolves the stubs of the program currently executing on this thread.
+        pub fn current_syscall_stubs() -> CurrentSyscallStubs {
+            CurrentSyscallStubs(Some(
+                match CURRENT_STUB_CONTEXTS.with(|contexts| {
+                    contexts.borrow().last().map(|(_, stubs)| stubs.clone())
+                }) {
+                    Some(stubs) => DispatchedStubs::Context(stubs),
+                    None => DispatchedStubs::Global(SYSCALL_STUBS.read().unwrap()),
+                },
+            ))
+        }
+
+        /// Syscalls exported by the loader that `SyscallStubs` has no methods for.
+        ///
+        /// Unless noted otherwise, the defaults behave like the `SyscallStubs`
+        /// ones: they log that the syscall is not available and fail.
+        pub trait LoaderSyscallStubs: SyscallStubs {
+            /// Defaults to the formatting `msg!` uses for five `u64`s natively.
+            fn sol_log_64(&self, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+                self.sol_log(&format!(
+                    "{arg1:#x}, {arg2:#x}, {arg3:#x}, {arg4:#x}, {arg5:#x}"
+                ));
+            }
+            /// Defaults to logging the base58 form of `pubkey`, as `Pubkey::log` does natively.
+            fn sol_log_pubkey(&self, pubkey: &Pubkey) {
+                self.sol_log(&pubkey.to_string());
+            }
+            /// Called when the program reports a panic. The panic itself is
+            /// recorded by `sol_panic_`; this only defaults to logging it.
+            fn sol_panic(&self, file: &str, line: u64, column: u64, message: &str) {
+                self.sol_log(&format!("panicked at {file}:{line}:{column}:\n{message}"));
+            }
+            fn sol_sha256(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.sol_log("SyscallStubs: sol_sha256() not available");
+                SYSCALL_FAILURE
+            }
+            fn sol_keccak256(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.sol_log("SyscallStubs: sol_keccak256() not available");
+                SYSCALL_FAILURE
+            }
+            fn sol_blake3(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.sol_log("SyscallStubs: sol_blake3() not available");
+                SYSCALL_FAILURE
+            }
+            /// Defaults to the native derivation in `Pubkey`.
+            fn sol_create_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+            ) -> u64 {
+                match Pubkey::create_program_address(seeds, program_id) {
+                    Ok(pda) => {
+                        *address = pda;
+                        0
+                    }
+                    Err(_) => SYSCALL_FAILURE,
+                }
+            }
+            /// Defaults to the native derivation in `Pubkey`.
+            fn sol_try_find_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+                bump_seed: &mut u8,
+            ) -> u64 {
+                match Pubkey::try_find_program_address(seeds, program_id) {
+                    Some((pda, bump)) => {
+                        *address = pda;
+                        *bump_seed = bump;
+                        0
+                    }
+                    None => SYSCALL_FAILURE,
+                }
+            }
+            /// Returns 0 or one of the `SECP256K1_RECOVER_ERROR_*` codes.
+            fn sol_secp256k1_recover(
+                &self,
+                _hash: &[u8; HASH_BYTES],
+                _recovery_id: u8,
+                _signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                _result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_secp256k1_recover() not available");
+                SECP256K1_RECOVER_ERROR_INVALID_SIGNATURE
+            }
+            /// Returns 0 if `point` is a valid point on the curve, 1 otherwise.
+            fn sol_curve_validate_point(
+                &self,
+                _curve_id: u64,
+                _point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_curve_validate_point() not available");
+                SYSCALL_FAILURE
+            }
+            /// For `CURVE25519_MUL`, `left_input` is the scalar and `right_input` the point.
+            fn sol_curve_group_op(
+                &self,
+                _curve_id: u64,
+                _group_op: u64,
+                _left_input: &[u8; CURVE25519_POINT_BYTES],
+                _right_input: &[u8; CURVE25519_POINT_BYTES],
+                _result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_curve_group_op() not available");
+                SYSCALL_FAILURE
+            }
+            fn sol_curve_multiscalar_mul(
+                &self,
+                _curve_id: u64,
+                _scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                _points: &[[u8; CURVE25519_POINT_BYTES]],
+                _result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_curve_multiscalar_mul() not available");
+                SYSCALL_FAILURE
+            }
+            /// `result` is sized by `alt_bn128_group_op_result_len(group_op)`.
+            fn sol_alt_bn128_group_op(
+                &self,
+                _group_op: u64,
+                _input: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_alt_bn128_group_op() not available");
+                SYSCALL_FAILURE
+            }
+            /// `result` is sized by `alt_bn128_compression_result_len(op)`.
+            fn sol_alt_bn128_compression(
+                &self,
+                _op: u64,
+                _input: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_alt_bn128_compression() not available");
+                SYSCALL_FAILURE
+            }
+            fn sol_poseidon(
+                &self,
+                _parameters: u64,
+                _endianness: u64,
+                _vals: &[&[u8]],
+                _hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_poseidon() not available");
+                SYSCALL_FAILURE
+            }
+            /// `result` is as long as `modulus`.
+            fn sol_big_mod_exp(
+                &self,
+                _base: &[u8],
+                _exponent: &[u8],
+                _modulus: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                self.sol_log("SyscallStubs: sol_big_mod_exp() not available");
+                SYSCALL_FAILURE
+            }
+            /// Called by `sol_invoke_signed_c` before the callee runs, as the
+            /// runtime starts every instruction with empty return data.
+            /// Defaults to setting empty return data.
+            fn sol_clear_return_data(&self) {
+                self.sol_set_return_data(&[]);
+            }
+        }
+
+        /// The Clock sysvar.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct ClockSysvar {
+            pub slot: u64,
+            pub epoch_start_timestamp: i64,
+            pub epoch: u64,
+            pub leader_schedule_epoch: u64,
+            pub unix_timestamp: i64,
+        }
+
+        impl ClockSysvar {
+            /// The `#[repr(C)]` `Clock` that `sol_get_clock_sysvar` writes.
+            pub fn to_bytes(&self) -> [u8; CLOCK_SYSVAR_SIZE] {
+                let mut bytes = [0; CLOCK_SYSVAR_SIZE];
+                bytes[0..8].copy_from_slice(&self.slot.to_ne_bytes());
+                bytes[8..16].copy_from_slice(&self.epoch_start_timestamp.to_ne_bytes());
+                bytes[16..24].copy_from_slice(&self.epoch.to_ne_bytes());
+                bytes[24..32].copy_from_slice(&self.leader_schedule_epoch.to_ne_bytes());
+                bytes[32..40].copy_from_slice(&self.unix_timestamp.to_ne_bytes());
+                bytes
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                [
+                    self.slot.to_le_bytes(),
+                    self.epoch_start_timestamp.to_le_bytes(),
+                    self.epoch.to_le_bytes(),
+                    self.leader_schedule_epoch.to_le_bytes(),
+                    self.unix_timestamp.to_le_bytes(),
+                ]
+                .concat()
+            }
+
+            /// Decodes the account data [`ClockSysvar::to_account_data`] produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                let data: &[u8; 40] = data.try_into().ok()?;
+                let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+                Some(Self {
+                    slot: u64_at(0),
+                    epoch_start_timestamp: u64_at(8) as i64,
+                    epoch: u64_at(16),
+                    leader_schedule_epoch: u64_at(24),
+                    unix_timestamp: u64_at(32) as i64,
+                })
+            }
+        }
+
+        /// The Rent sysvar. The default is the runtime's default rent.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub struct RentSysvar {
+            pub lamports_per_byte_year: u64,
+            pub exemption_threshold: f64,
+            pub burn_percent: u8,
+        }
+
+        impl Default for RentSysvar {
+            fn default() -> Self {
+                Self {
+                    lamports_per_byte_year: 3_480,
+                    exemption_threshold: 2.0,
+                    burn_percent: 50,
+                }
+            }
+        }
+
+        impl RentSysvar {
+            /// The `#[repr(C)]` `Rent` that `sol_get_rent_sysvar` writes.
+            pub fn to_bytes(&self) -> [u8; RENT_SYSVAR_SIZE] {
+                let mut bytes = [0; RENT_SYSVAR_SIZE];
+                bytes[0..8].copy_from_slice(&self.lamports_per_byte_year.to_ne_bytes());
+                bytes[8..16].copy_from_slice(&self.exemption_threshold.to_ne_bytes());
+                bytes[16] = self.burn_percent;
+                bytes
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                let mut data = Vec::with_capacity(17);
+                data.extend(self.lamports_per_byte_year.to_le_bytes());
+                data.extend(self.exemption_threshold.to_le_bytes());
+                data.push(self.burn_percent);
+                data
+            }
+
+            /// Decodes the account data [`RentSysvar::to_account_data`] produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                let data: &[u8; 17] = data.try_into().ok()?;
+                let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+                Some(Self {
+                    lamports_per_byte_year: u64_at(0),
+                    exemption_threshold: f64::from_bits(u64_at(8)),
+                    burn_percent: data[16],
+                })
+            }
+        }
+
+        /// The EpochSchedule sysvar. The default is the runtime's default schedule.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct EpochScheduleSysvar {
+            pub slots_per_epoch: u64,
+            pub leader_schedule_slot_offset: u64,
+            pub warmup: bool,
+            pub first_normal_epoch: u64,
+            pub first_normal_slot: u64,
+        }
+
+        impl Default for EpochScheduleSysvar {
+            fn default() -> Self {
+                Self {
+                    slots_per_epoch: 432_000,
+                    leader_schedule_slot_offset: 432_000,
+                    warmup: true,
+                    first_normal_epoch: 14,
+                    first_normal_slot: 524_256,
+                }
+            }
+        }
+
+        impl EpochScheduleSysvar {
+            /// The `#[repr(C)]` `EpochSchedule` that `sol_get_epoch_schedule_sysvar` writes.
+            pub fn to_bytes(&self) -> [u8; EPOCH_SCHEDULE_SYSVAR_SIZE] {
+                let mut bytes = [0; EPOCH_SCHEDULE_SYSVAR_SIZE];
+                bytes[0..8].copy_from_slice(&self.slots_per_epoch.to_ne_bytes());
+                bytes[8..16].copy_from_slice(&self.leader_schedule_slot_offset.to_ne_bytes());
+                bytes[16] = self.warmup as u8;
+                bytes[24..32].copy_from_slice(&self.first_normal_epoch.to_ne_bytes());
+                bytes[32..40].copy_from_slice(&self.first_normal_slot.to_ne_bytes());
+                bytes
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                let mut data = Vec::with_capacity(33);
+                data.extend(self.slots_per_epoch.to_le_bytes());
+                data.extend(self.leader_schedule_slot_offset.to_le_bytes());
+                data.push(self.warmup as u8);
+                data.extend(self.first_normal_epoch.to_le_bytes());
+                data.extend(self.first_normal_slot.to_le_bytes());
+                data
+            }
+
+            /// Decodes the account data [`EpochScheduleSysvar::to_account_data`]
+            /// produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                let data: &[u8; 33] = data.try_into().ok()?;
+                let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+                Some(Self {
+                    slots_per_epoch: u64_at(0),
+                    leader_schedule_slot_offset: u64_at(8),
+                    warmup: match data[16] {
+                        0 => false,
+                        1 => true,
+                        _ => return None,
+                    },
+                    first_normal_epoch: u64_at(17),
+                    first_normal_slot: u64_at(25),
+                })
+            }
+        }
+
+        /// The EpochRewards sysvar.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct EpochRewardsSysvar {
+            pub distribution_starting_block_height: u64,
+            pub num_partitions: u64,
+            pub parent_blockhash: [u8; HASH_BYTES],
+            pub total_points: u128,
+            pub total_rewards: u64,
+            pub distributed_rewards: u64,
+            pub active: bool,
+        }
+
+        impl EpochRewardsSysvar {
+            /// The `#[repr(C, align(16))]` `EpochRewards` that
+            /// `sol_get_epoch_rewards_sysvar` writes.
+            pub fn to_bytes(&self) -> [u8; EPOCH_REWARDS_SYSVAR_SIZE] {
+                let mut bytes = [0; EPOCH_REWARDS_SYSVAR_SIZE];
+                bytes[0..8].copy_from_slice(&self.distribution_starting_block_height.to_ne_bytes());
+                bytes[8..16].copy_from_slice(&self.num_partitions.to_ne_bytes());
+                bytes[16..48].copy_from_slice(&self.parent_blockhash);
+                bytes[48..64].copy_from_slice(&self.total_points.to_ne_bytes());
+                bytes[64..72].copy_from_slice(&self.total_rewards.to_ne_bytes());
+                bytes[72..80].copy_from_slice(&self.distributed_rewards.to_ne_bytes());
+                bytes[80] = self.active as u8;
+                bytes
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                let mut data = Vec::with_capacity(81);
+                data.extend(self.distribution_starting_block_height.to_le_bytes());
+                data.extend(self.num_partitions.to_le_bytes());
+                data.extend(self.parent_blockhash);
+                data.extend(self.total_points.to_le_bytes());
+                data.extend(self.total_rewards.to_le_bytes());
+                data.extend(self.distributed_rewards.to_le_bytes());
+                data.push(self.active as u8);
+                data
+            }
+
+            /// Decodes the account data [`EpochRewardsSysvar::to_account_data`]
+            /// produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                let data: &[u8; 81] = data.try_into().ok()?;
+                let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+                Some(Self {
+                    distribution_starting_block_height: u64_at(0),
+                    num_partitions: u64_at(8),
+                    parent_blockhash: data[16..48].try_into().unwrap(),
+                    total_points: u128::from_le_bytes(data[48..64].try_into().unwrap()),
+                    total_rewards: u64_at(64),
+                    distributed_rewards: u64_at(72),
+                    active: match data[80] {
+                        0 => false,
+                        1 => true,
+                        _ => return None,
+                    },
+                })
+            }
+        }
+
+        /// The LastRestartSlot sysvar.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct LastRestartSlotSysvar {
+            pub last_restart_slot: u64,
+        }
+
+        impl LastRestartSlotSysvar {
+            /// The `#[repr(C)]` `LastRestartSlot` that `sol_get_last_restart_slot` writes.
+            pub fn to_bytes(&self) -> [u8; LAST_RESTART_SLOT_SYSVAR_SIZE] {
+                self.last_restart_slot.to_ne_bytes()
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                self.last_restart_slot.to_le_bytes().to_vec()
+            }
+
+            /// Decodes the account data
+            /// [`LastRestartSlotSysvar::to_account_data`] produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                Some(Self {
+                    last_restart_slot: u64::from_le_bytes(data.try_into().ok()?),
+                })
+            }
+        }
+
+        /// The deprecated Fees sysvar.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct FeesSysvar {
+            pub lamports_per_signature: u64,
+        }
+
+        impl FeesSysvar {
+            /// The `#[repr(C)]` `Fees` that `sol_get_fees_sysvar` writes.
+            pub fn to_bytes(&self) -> [u8; FEES_SYSVAR_SIZE] {
+                self.lamports_per_signature.to_ne_bytes()
+            }
+
+            /// The bincode-serialized account data `sol_get_sysvar` reads from.
+            pub fn to_account_data(&self) -> Vec<u8> {
+                self.lamports_per_signature.to_le_bytes().to_vec()
+            }
+
+            /// Decodes the account data [`FeesSysvar::to_account_data`] produces.
+            pub fn from_account_data(data: &[u8]) -> Option<Self> {
+                Some(Self {
+                    lamports_per_signature: u64::from_le_bytes(data.try_into().ok()?),
+                })
+            }
+        }
+
+        /// One epoch's entry in the StakeHistory sysvar.
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct StakeHistoryEntry {
+            pub effective: u64,
+            pub activating: u64,
+            pub deactivating: u64,
+        }
+
+        /// Typed sysvar values served through the sysvar syscalls.
+        ///
+        /// Each `sol_get_*_sysvar` getter writes the runtime's layout of its
+        /// sysvar to `var_addr` and returns `0`, or returns `UNSUPPORTED_SYSVAR`
+        /// when the sysvar hasn't been set. [`SysvarProvider::sol_get_sysvar`]
+        /// serves windows of the sysvars' account data by id, including
+        /// SlotHashes and StakeHistory, which have no getter of their own. A
+        /// `SyscallStubs` implementation can forward its syscalls straight to
+        /// these, and [`NativeSyscallStubs::with_sysvar_provider`] does so.
+        #[derive(Debug, Clone, Default)]
+        pub struct SysvarProvider {
+            pub clock: Option<ClockSysvar>,
+            pub rent: Option<RentSysvar>,
+            pub epoch_schedule: Option<EpochScheduleSysvar>,
+            pub epoch_rewards: Option<EpochRewardsSysvar>,
+            pub last_restart_slot: Option<LastRestartSlotSysvar>,
+            pub fees: Option<FeesSysvar>,
+            /// `(slot, hash)` pairs, most recent slot first.
+            pub slot_hashes: Option<Vec<(u64, [u8; HASH_BYTES])>>,
+            /// `(epoch, entry)` pairs, most recent epoch first.
+            pub stake_history: Option<Vec<(u64, StakeHistoryEntry)>>,
+        }
+
+        impl SysvarProvider {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            /// A provider serving every sysvar with its default value.
+            pub fn with_defaults() -> Self {
+                Self {
+                    clock: Some(ClockSysvar::default()),
+                    rent: Some(RentSysvar::default()),
+                    epoch_schedule: Some(EpochScheduleSysvar::default()),
+                    epoch_rewards: Some(EpochRewardsSysvar::default()),
+                    last_restart_slot: Some(LastRestartSlotSysvar::default()),
+                    fees: Some(FeesSysvar::default()),
+                    slot_hashes: Some(Vec::new()),
+                    stake_history: Some(Vec::new()),
+                }
+            }
+
+            pub fn with_clock(mut self, clock: ClockSysvar) -> Self {
+                self.clock = Some(clock);
+                self
+            }
+
+            pub fn with_rent(mut self, rent: RentSysvar) -> Self {
+                self.rent = Some(rent);
+                self
+            }
+
+            pub fn with_epoch_schedule(mut self, epoch_schedule: EpochScheduleSysvar) -> Self {
+                self.epoch_schedule = Some(epoch_schedule);
+                self
+            }
+
+            pub fn with_epoch_rewards(mut self, epoch_rewards: EpochRewardsSysvar) -> Self {
+                self.epoch_rewards = Some(epoch_rewards);
+                self
+            }
+
+            pub fn with_last_restart_slot(mut self, last_restart_slot: LastRestartSlotSysvar) -> Self {
+                self.last_restart_slot = Some(last_restart_slot);
+                self
+            }
+
+            pub fn with_fees(mut self, fees: FeesSysvar) -> Self {
+                self.fees = Some(fees);
+                self
+            }
+
+            pub fn with_slot_hashes(mut self, slot_hashes: Vec<(u64, [u8; HASH_BYTES])>) -> Self {
+                self.slot_hashes = Some(slot_hashes);
+                self
+            }
+
+            pub fn with_stake_history(mut self, stake_history: Vec<(u64, StakeHistoryEntry)>) -> Self {
+                self.stake_history = Some(stake_history);
+                self
+            }
+
+            /// The account data of sysvar `id`, as `sol_get_sysvar` reads it,
+            /// or `None` if it isn't set.
+            ///
+            /// SlotHashes and StakeHistory are zero-filled to the size of their
+            /// accounts, which hold up to 512 entries; extra entries are dropped.
+            pub fn account_data(&self, id: &[u8; 32]) -> Option<Vec<u8>> {
+                match *id {
+                    CLOCK_SYSVAR_ID => self.clock.map(|clock| clock.to_account_data()),
+                    RENT_SYSVAR_ID => self.rent.map(|rent| rent.to_account_data()),
+                    EPOCH_SCHEDULE_SYSVAR_ID => self
+                        .epoch_schedule
+                        .map(|epoch_schedule| epoch_schedule.to_account_data()),
+                    EPOCH_REWARDS_SYSVAR_ID => self
+                        .epoch_rewards
+                        .map(|epoch_rewards| epoch_rewards.to_account_data()),
+                    LAST_RESTART_SLOT_SYSVAR_ID => self
+                        .last_restart_slot
+                        .map(|last_restart_slot| last_restart_slot.to_account_data()),
+                    FEES_SYSVAR_ID => self.fees.map(|fees| fees.to_account_data()),
+                    SLOT_HASHES_SYSVAR_ID => self.slot_hashes.as_ref().map(|slot_hashes| {
+                        let entries = &slot_hashes[..slot_hashes.len().min(SLOT_HASHES_MAX_ENTRIES)];
+                        let mut data = Vec::with_capacity(8 + SLOT_HASHES_MAX_ENTRIES * 40);
+                        data.extend((entries.len() as u64).to_le_bytes());
+                        for (slot, hash) in entries {
+                            data.extend(slot.to_le_bytes());
+                            data.extend(hash);
+                        }
+                        data.resize(8 + SLOT_HASHES_MAX_ENTRIES * 40, 0);
+                        data
+                    }),
+                    STAKE_HISTORY_SYSVAR_ID => self.stake_history.as_ref().map(|stake_history| {
+                        let entries =
+                            &stake_history[..stake_history.len().min(STAKE_HISTORY_MAX_ENTRIES)];
+                        let mut data = Vec::with_capacity(8 + STAKE_HISTORY_MAX_ENTRIES * 32);
+                        data.extend((entries.len() as u64).to_le_bytes());
+                        for (epoch, entry) in entries {
+                            data.extend(epoch.to_le_bytes());
+                            data.extend(entry.effective.to_le_bytes());
+                            data.extend(entry.activating.to_le_bytes());
+                            data.extend(entry.deactivating.to_le_bytes());
+                        }
+                        data.resize(8 + STAKE_HISTORY_MAX_ENTRIES * 32, 0);
+                        data
+                    }),
+                    _ => None,
+                }
+            }
+
+            /// Copies `length` bytes at `offset` of sysvar `sysvar_id_addr`'s
+            /// account data to `var_addr`. Returns `SYSVAR_NOT_FOUND` for
+            /// sysvars that aren't set and `OFFSET_LENGTH_EXCEEDS_SYSVAR` for
+            /// windows that don't fit in the data, as the runtime does.
+            ///
+            /// # Safety
+            ///
+            /// `sysvar_id_addr` must point to 32 bytes and `var_addr` to
+            /// `length` writable bytes.
+            pub unsafe fn sol_get_sysvar(
+                &self,
+                sysvar_id_addr: *const u8,
+                var_addr: *mut u8,
+                offset: u64,
+                length: u64,
+            ) -> u64 {
+                let id = unsafe { &*(sysvar_id_addr as *const [u8; 32]) };
+                let Some(data) = self.account_data(id) else {
+                    return SYSVAR_NOT_FOUND;
+                };
+                match offset.checked_add(length) {
+                    Some(end) if end <= data.len() as u64 => {
+                        unsafe {
+                            std::ptr::copy_nonoverlapping(
+                                data[offset as usize..].as_ptr(),
+                                var_addr,
+                                length as usize,
+                            )
+                        };
+                        0
+                    }
+                    _ => OFFSET_LENGTH_EXCEEDS_SYSVAR,
+                }
+            }
+
+            fn write<const N: usize>(bytes: Option<[u8; N]>, var_addr: *mut u8) -> u64 {
+                match bytes {
+                    Some(bytes) => {
+                        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), var_addr, N) };
+                        0
+                    }
+                    None => UNSUPPORTED_SYSVAR,
+                }
+            }
+
+            pub fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+                Self::write(self.clock.map(|clock| clock.to_bytes()), var_addr)
+            }
+
+            pub fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                Self::write(self.rent.map(|rent| rent.to_bytes()), var_addr)
+            }
+
+            pub fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+                Self::write(
+                    self.epoch_schedule
+                        .map(|epoch_schedule| epoch_schedule.to_bytes()),
+                    var_addr,
+                )
+            }
+
+            pub fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+                Self::write(
+                    self.epoch_rewards.map(|epoch_rewards| epoch_rewards.to_bytes()),
+                    var_addr,
+                )
+            }
+
+            pub fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+                Self::write(
+                    self.last_restart_slot
+                        .map(|last_restart_slot| last_restart_slot.to_bytes()),
+                    var_addr,
+                )
+            }
+
+            pub fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+                Self::write(self.fees.map(|fees| fees.to_bytes()), var_addr)
+            }
+        }
+
+        /// The default stubs: everything that can be done without a runtime is
+        /// done natively.
+        ///
+        /// Memory operations are real, logs go to stderr with the runtime's
+        /// prefixes, sysvars are served from the [`SysvarProvider`] set with
+        /// [`NativeSyscallStubs::with_sysvar_provider`] or else from the account
+        /// data configured with [`NativeSyscallStubs::with_sysvar`], and return data
+        /// is kept in memory.
+        /// CPI and the syscalls that need a ledger keep the `SyscallStubs`
+        /// defaults. To override only some syscalls, wrap this type and forward
+        /// everything else to it.
+        #[derive(Default)]
+        pub struct NativeSyscallStubs {
+            program_id: Pubkey,
+            sysvar_provider: SysvarProvider,
+            sysvars: RwLock<std::collections::HashMap<[u8; 32], Vec<u8>>>,
+            /// The sysvars set with [`NativeSyscallStubs::with_sysvar`] that
+            /// have a getter, decoded from their account data.
+            decoded_sysvars: RwLock<SysvarProvider>,
+            return_data: RwLock<Option<(Pubkey, Vec<u8>)>>,
+        }
+
+        impl NativeSyscallStubs {
+            /// Sets the program id reported alongside return data.
+            pub fn with_program_id(mut self, program_id: Pubkey) -> Self {
+                self.program_id = program_id;
+                self
+            }
+
+            /// Serves the typed sysvars in `sysvar_provider` ahead of those set
+            /// with [`NativeSyscallStubs::with_sysvar`].
+            pub fn with_sysvar_provider(mut self, sysvar_provider: SysvarProvider) -> Self {
+                self.sysvar_provider = sysvar_provider;
+                self
+            }
+
+            /// Serves `data` as the bincode-serialized account data of sysvar
+            /// `id`. `sol_get_sysvar` reads windows of it as is. The
+            /// `sol_get_*_sysvar` getters write the runtime's layout of the
+            /// value it decodes to, and return `UNSUPPORTED_SYSVAR` if it
+            /// doesn't decode, as when it has the wrong length.
+            pub fn with_sysvar(self, id: [u8; 32], data: Vec<u8>) -> Self {
+                self.set_sysvar(id, data);
+                self
+            }
+
+            pub fn set_sysvar(&self, id: [u8; 32], data: Vec<u8>) {
+                let mut decoded = self.decoded_sysvars.write().unwrap();
+                match id {
+                    CLOCK_SYSVAR_ID => decoded.clock = ClockSysvar::from_account_data(&data),
+                    RENT_SYSVAR_ID => decoded.rent = RentSysvar::from_account_data(&data),
+                    EPOCH_SCHEDULE_SYSVAR_ID => {
+                        decoded.epoch_schedule = EpochScheduleSysvar::from_account_data(&data)
+                    }
+                    EPOCH_REWARDS_SYSVAR_ID => {
+                        decoded.epoch_rewards = EpochRewardsSysvar::from_account_data(&data)
+                    }
+                    LAST_RESTART_SLOT_SYSVAR_ID => {
+                        decoded.last_restart_slot = LastRestartSlotSysvar::from_account_data(&data)
+                    }
+                    FEES_SYSVAR_ID => decoded.fees = FeesSysvar::from_account_data(&data),
+                    _ => {}
+                }
+                self.sysvars.write().unwrap().insert(id, data);
+            }
+        }
+
+        impl SyscallStubs for NativeSyscallStubs {
+            fn sol_log(&self, message: &str) {
+                eprintln!("Program log: {message}");
+            }
+            fn sol_log_data(&self, fields: &[&[u8]]) {
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|field| $crate::loader_side::base64_encode(field))
+                    .collect();
+                eprintln!("Program data: {}", fields.join(" "));
+            }
+            // `SyscallStubs` fixes the signature; the pointers are the ones the
+            // program passed to the syscall.
+            #[allow(clippy::not_unsafe_ptr_arg_deref)]
+            fn sol_get_sysvar(
+                &self,
+                sysvar_id_addr: *const u8,
+                var_addr: *mut u8,
+                offset: u64,
+                length: u64,
+            ) -> u64 {
+                let result = unsafe {
+                    self.sysvar_provider
+                        .sol_get_sysvar(sysvar_id_addr, var_addr, offset, length)
+                };
+                if result != SYSVAR_NOT_FOUND {
+                    return result;
+                }
+                let id = unsafe { &*(sysvar_id_addr as *const [u8; 32]) };
+                let sysvars = self.sysvars.read().unwrap();
+                let Some(data) = sysvars.get(id) else {
+                    return SYSVAR_NOT_FOUND;
+                };
+                match offset.checked_add(length) {
+                    Some(end) if end <= data.len() as u64 => {
+                        unsafe {
+                            std::ptr::copy_nonoverlapping(
+                                data[offset as usize..].as_ptr(),
+                                var_addr,
+                                length as usize,
+                            )
+                        };
+                        0
+                    }
+                    _ => OFFSET_LENGTH_EXCEEDS_SYSVAR,
+                }
+            }
+            fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_clock_sysvar(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_clock_sysvar(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_epoch_schedule_sysvar(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_epoch_schedule_sysvar(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_fees_sysvar(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_fees_sysvar(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_rent_sysvar(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_rent_sysvar(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_epoch_rewards_sysvar(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_epoch_rewards_sysvar(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+                match self.sysvar_provider.sol_get_last_restart_slot(var_addr) {
+                    UNSUPPORTED_SYSVAR => {
+                        self.decoded_sysvars.read().unwrap().sol_get_last_restart_slot(var_addr)
+                    }
+                    result => result,
+                }
+            }
+            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                self.return_data.read().unwrap().clone()
+            }
+            fn sol_set_return_data(&self, data: &[u8]) {
+                *self.return_data.write().unwrap() = if data.is_empty() {
+                    None
+                } else {
+                    Some((self.program_id, data.to_vec()))
+                };
+            }
+        }
+
+        impl LoaderSyscallStubs for NativeSyscallStubs {}
+
         pub struct UnimplementedSyscallStubs {}
         impl SyscallStubs for UnimplementedSyscallStubs {
             fn sol_get_clock_sysvar(&self, _var_addr: *mut u8) -> u64 {
                 unimplemented!()
             }
-            fn sol_get_epoch_rewards_sysvar(&self, _var_addr: *mut u8) -> u64 {
-                unimplemented!()
+            fn sol_get_epoch_rewards_sysvar(&self, _var_addr: *mut u8) -> u64 {
+                unimplemented!()
+            }
+            fn sol_get_epoch_schedule_sysvar(&self, _var_addr: *mut u8) -> u64 {
+                unimplemented!()
+            }
+            fn sol_get_fees_sysvar(&self, _var_addr: *mut u8) -> u64 {
+                unimplemented!()
+            }
+            fn sol_get_last_restart_slot(&self, _var_addr: *mut u8) -> u64 {
+                unimplemented!()
+            }
+            fn sol_get_processed_sibling_instruction(&self, _index: usize) -> Option<Instruction> {
+                unimplemented!()
+            }
+            fn sol_get_rent_sysvar(&self, _var_addr: *mut u8) -> u64 {
+                unimplemented!()
+            }
+            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                unimplemented!()
+            }
+            fn sol_get_stack_height(&self) -> u64 {
+                unimplemented!()
+            }
+            fn sol_invoke_signed(
+                &self,
+                _instruction: &Instruction,
+                _account_infos: &[AccountInfo],
+                _signers_seeds: &[&[&[u8]]],
+            ) -> ProgramResult {
+                unimplemented!()
+            }
+            fn sol_log(&self, _message: &str) {
+                unimplemented!()
+            }
+            fn sol_log_compute_units(&self) {
+                unimplemented!()
+            }
+            fn sol_log_data(&self, _fields: &[&[u8]]) {
+                unimplemented!()
+            }
+            unsafe fn sol_memcmp(
+                &self,
+                _s1: *const u8,
+                _s2: *const u8,
+                _n: usize,
+                _result: *mut i32,
+            ) {
+                unimplemented!()
+            }
+            unsafe fn sol_memcpy(&self, _dst: *mut u8, _src: *const u8, _n: usize) {
+                unimplemented!()
+            }
+            unsafe fn sol_memmove(&self, _dst: *mut u8, _src: *const u8, _n: usize) {
+                unimplemented!()
+            }
+            unsafe fn sol_memset(&self, _s: *mut u8, _c: u8, _n: usize) {
+                unimplemented!()
+            }
+            fn sol_remaining_compute_units(&self) -> u64 {
+                unimplemented!()
+            }
+            fn sol_set_return_data(&self, _data: &[u8]) {
+                unimplemented!()
+            }
+        }
+        impl LoaderSyscallStubs for UnimplementedSyscallStubs {
+            fn sol_log_64(&self, _arg1: u64, _arg2: u64, _arg3: u64, _arg4: u64, _arg5: u64) {
+                unimplemented!()
+            }
+            fn sol_log_pubkey(&self, _pubkey: &Pubkey) {
+                unimplemented!()
+            }
+            // Panicking here would abort the loader while it is reporting a panic.
+            fn sol_panic(&self, _file: &str, _line: u64, _column: u64, _message: &str) {}
+            fn sol_sha256(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                unimplemented!()
+            }
+            fn sol_keccak256(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                unimplemented!()
+            }
+            fn sol_blake3(&self, _vals: &[&[u8]], _hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                unimplemented!()
+            }
+            fn sol_create_program_address(
+                &self,
+                _seeds: &[&[u8]],
+                _program_id: &Pubkey,
+                _address: &mut Pubkey,
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_try_find_program_address(
+                &self,
+                _seeds: &[&[u8]],
+                _program_id: &Pubkey,
+                _address: &mut Pubkey,
+                _bump_seed: &mut u8,
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_secp256k1_recover(
+                &self,
+                _hash: &[u8; HASH_BYTES],
+                _recovery_id: u8,
+                _signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                _result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_curve_validate_point(
+                &self,
+                _curve_id: u64,
+                _point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_curve_group_op(
+                &self,
+                _curve_id: u64,
+                _group_op: u64,
+                _left_input: &[u8; CURVE25519_POINT_BYTES],
+                _right_input: &[u8; CURVE25519_POINT_BYTES],
+                _result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_curve_multiscalar_mul(
+                &self,
+                _curve_id: u64,
+                _scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                _points: &[[u8; CURVE25519_POINT_BYTES]],
+                _result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_alt_bn128_group_op(
+                &self,
+                _group_op: u64,
+                _input: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_alt_bn128_compression(
+                &self,
+                _op: u64,
+                _input: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_poseidon(
+                &self,
+                _parameters: u64,
+                _endianness: u64,
+                _vals: &[&[u8]],
+                _hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_big_mod_exp(
+                &self,
+                _base: &[u8],
+                _exponent: &[u8],
+                _modulus: &[u8],
+                _result: &mut [u8],
+            ) -> u64 {
+                unimplemented!()
+            }
+            fn sol_clear_return_data(&self) {
+                unimplemented!()
+            }
+        }
+
+        /// A panic reported by the program through `sol_panic_`.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct ProgramPanic {
+            pub file: String,
+            pub line: u64,
+            pub column: u64,
+            pub message: String,
+        }
+
+        impl std::fmt::Display for ProgramPanic {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(
+                    f,
+                    "panicked at {}:{}:{}:\n{}",
+                    self.file, self.line, self.column, self.message
+                )
+            }
+        }
+
+        /// Why a program run by [`invoke_entrypoint`] stopped without returning.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum ProgramAbort {
+            /// The program panicked.
+            Panic(ProgramPanic),
+            /// A syscall was charged more compute units than the budget had left.
+            ComputeBudgetExceeded { budget: u64 },
+            /// The program made a syscall the replayed trace doesn't have.
+            ReplayDiverged(String),
+            /// The program set more than `MAX_RETURN_DATA` bytes of return data.
+            ReturnDataTooLarge { len: u64 },
+        }
+
+        impl From<ProgramPanic> for ProgramAbort {
+            fn from(panic: ProgramPanic) -> Self {
+                Self::Panic(panic)
+            }
+        }
+
+        impl std::fmt::Display for ProgramAbort {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Self::Panic(panic) => panic.fmt(f),
+                    Self::ComputeBudgetExceeded { budget } => {
+                        write!(f, "exceeded the compute budget of {budget} units")
+                    }
+                    Self::ReplayDiverged(reason) => f.write_str(reason),
+                    Self::ReturnDataTooLarge { len } => {
+                        write!(f, "Return data too large ({len} > {MAX_RETURN_DATA})")
+                    }
+                }
+            }
+        }
+
+        impl std::error::Error for ProgramAbort {}
+
+        std::thread_local! {
+            /// One slot per [`invoke_entrypoint`] running on this thread,
+            /// innermost last, holding the abort that stopped its program.
+            static PROGRAM_ABORTS: std::cell::RefCell<Vec<Option<ProgramAbort>>> =
+                const { std::cell::RefCell::new(Vec::new()) };
+
+            /// The abort requested by the syscall being dispatched on this thread.
+            static REQUESTED_ABORT: std::cell::RefCell<Option<ProgramAbort>> =
+                const { std::cell::RefCell::new(None) };
+        }
+
+        /// Aborts the program running on this thread: once the current syscall
+        /// returns, the program unwinds to its `catch_program_abort` and its
+        /// [`invoke_entrypoint`] returns `Err(abort)`. Only the first abort of
+        /// an invocation is kept.
+        ///
+        /// Returns right away and does nothing outside `invoke_entrypoint`.
+        pub fn abort_program(abort: ProgramAbort) {
+            PROGRAM_ABORTS.with(|aborts| {
+                if let Some(slot @ None) = aborts.borrow_mut().last_mut() {
+                    *slot = Some(abort);
+                }
+            });
+        }
+
+        /// Asks for the program running on this thread to be aborted once the
+        /// syscall being dispatched has released its stubs. Stubs call this
+        /// rather than [`abort_program`], then fail the syscall and return.
+        /// Only the first request of a syscall is kept.
+        ///
+        /// Does nothing outside [`invoke_entrypoint`].
+        pub fn request_program_abort(abort: ProgramAbort) {
+            if PROGRAM_ABORTS.with(|aborts| aborts.borrow().is_empty()) {
+                return;
+            }
+            REQUESTED_ABORT.with(|requested| {
+                requested.borrow_mut().get_or_insert(abort);
+            });
+        }
+
+        /// Whether the program running on this thread has been aborted.
+        pub fn is_program_aborted() -> bool {
+            PROGRAM_ABORTS.with(|aborts| matches!(aborts.borrow().last(), Some(Some(_))))
+        }
+
+        /// Pubkeys that `sol_get_processed_sibling_instruction` points the
+        /// program's account metas at, and account data that stubs resize
+        /// during a CPI. The program reads them through raw pointers, so they
+        /// stay alive until the top-level instruction ends. Each distinct
+        /// pubkey is stored once.
+        #[derive(Default)]
+        pub struct InstructionArena {
+            pubkeys: std::sync::Mutex<std::collections::HashMap<Pubkey, Box<Pubkey>>>,
+            data: std::sync::Mutex<Vec<Box<[u8]>>>,
+        }
+
+        impl InstructionArena {
+            /// Returns an address holding `pubkey` that stays valid as long as
+            /// the arena does.
+            pub fn alloc_pubkey(&self, pubkey: Pubkey) -> *const Pubkey {
+                let mut pubkeys = self.pubkeys.lock().unwrap();
+                &**pubkeys.entry(pubkey).or_insert_with(|| Box::new(pubkey)) as *const Pubkey
+            }
+
+            /// Moves `data` into the arena and returns where it now lives,
+            /// valid for writes as long as the arena is.
+            pub fn alloc_data(&self, data: Vec<u8>) -> *mut [u8] {
+                let mut buffers = self.data.lock().unwrap();
+                buffers.push(data.into_boxed_slice());
+                &mut **buffers.last_mut().unwrap() as *mut [u8]
+            }
+
+            /// How many distinct pubkeys the arena holds.
+            pub fn len(&self) -> usize {
+                self.pubkeys.lock().unwrap().len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+        }
+
+        lazy_static::lazy_static! {
+            /// Arena used when no instruction is running on the calling thread.
+            /// It is never freed, but holds each pubkey only once.
+            static ref UNSCOPED_INSTRUCTION_ARENA: Arc<InstructionArena> = Arc::default();
+        }
+
+        std::thread_local! {
+            /// Arena of the top-level instruction running on this thread.
+            static CURRENT_INSTRUCTION_ARENA: std::cell::RefCell<Option<Arc<InstructionArena>>> =
+                const { std::cell::RefCell::new(None) };
+        }
+
+        /// Returns the arena of the instruction running on this thread.
+        pub fn current_instruction_arena() -> Arc<InstructionArena> {
+            CURRENT_INSTRUCTION_ARENA
+                .with(|arena| arena.borrow().clone())
+                .unwrap_or_else(|| UNSCOPED_INSTRUCTION_ARENA.clone())
+        }
+
+        /// Gives this thread a fresh [`InstructionArena`] until the returned
+        /// guard is dropped, which frees it. [`invoke_entrypoint`] does this
+        /// for top-level instructions; loaders that call the entrypoint
+        /// directly can do it themselves.
+        pub fn enter_instruction_arena() -> InstructionArenaGuard {
+            let previous =
+                CURRENT_INSTRUCTION_ARENA.with(|arena| arena.borrow_mut().replace(Arc::default()));
+            InstructionArenaGuard {
+                previous,
+                _not_send: std::marker::PhantomData,
+            }
+        }
+
+        /// Restores the previous arena when dropped.
+        #[must_use]
+        pub struct InstructionArenaGuard {
+            previous: Option<Arc<InstructionArena>>,
+            _not_send: std::marker::PhantomData<*const ()>,
+        }
+
+        impl Drop for InstructionArenaGuard {
+            fn drop(&mut self) {
+                let previous = self.previous.take();
+                CURRENT_INSTRUCTION_ARENA.with(|arena| *arena.borrow_mut() = previous);
+            }
+        }
+
+        /// Runs a program's `entrypoint` on the calling thread and returns
+        /// `Err` if the program was aborted: it panicked and reported it
+        /// through `sol_panic_`, or a syscall called [`abort_program`].
+        ///
+        /// An aborted program stops only if its entrypoint runs it through
+        /// `catch_program_abort`, as the `stubs_entrypoint` exported by
+        /// `declare_sol_app_stubs!(process_instruction)` does. Otherwise it
+        /// runs on after a syscall aborts it, and a panic unwinding out of its
+        /// `extern "C"` entrypoint ends the process.
+        ///
+        /// A CPI made through this function shares the caller's
+        /// [`InstructionArena`]; a top-level call gets a fresh one that is
+        /// freed when it returns.
+        ///
+        /// # Safety
+        ///
+        /// `input` is handed to `entrypoint` untouched, so it must be what the
+        /// entrypoint expects: for a program entrypoint, a writable buffer
+        /// holding the input serialized in the runtime's layout, aligned to 8
+        /// bytes, with the `MAX_PERMITTED_DATA_INCREASE` spare bytes after
+        /// every account's data, and valid until the call returns.
+        pub unsafe fn invoke_entrypoint(
+            entrypoint: unsafe extern "C" fn(*mut u8) -> u64,
+            input: *mut u8,
+        ) -> Result<u64, ProgramAbort> {
+            let _arena = CURRENT_INSTRUCTION_ARENA
+                .with(|arena| arena.borrow().is_none())
+                .then(enter_instruction_arena);
+            PROGRAM_ABORTS.with(|aborts| aborts.borrow_mut().push(None));
+            let result = unsafe { entrypoint(input) };
+            match PROGRAM_ABORTS.with(|aborts| aborts.borrow_mut().pop().flatten()) {
+                Some(abort) => Err(abort),
+                None => Ok(result),
+            }
+        }
+
+        /// Why [`ProgramLibraries::load`] could not load a program.
+        #[derive(Debug)]
+        pub enum LoadProgramError {
+            /// `dlopen` failed; carries the `dlerror` text.
+            Open(String),
+            /// The library does not export the named symbol.
+            MissingSymbol(&'static str),
+            /// The program's `set_stubs` rejected the table with this code.
+            SetStubs(u64),
+            /// Copying the library to load a second, isolated instance failed.
+            Copy(std::io::Error),
+            /// The program's `stubs_abi_fingerprint` differs from the loader's
+            /// [`SYSCALL_STUBS_ABI_FINGERPRINT`].
+            LayoutMismatch { loader: u64, program: u64 },
+        }
+
+        impl std::fmt::Display for LoadProgramError {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Self::Open(reason) => write!(f, "failed to open the program library: {reason}"),
+                    Self::MissingSymbol(symbol) => {
+                        write!(f, "the program library does not export `{symbol}`")
+                    }
+                    Self::SetStubs(code) => match SetStubsError::reason(*code) {
+                        Some(reason) => write!(f, "set_stubs rejected the table: {reason}"),
+                        None => write!(f, "set_stubs failed with code {code}"),
+                    },
+                    Self::Copy(error) => write!(f, "failed to copy the program library: {error}"),
+                    Self::LayoutMismatch { loader, program } => write!(
+                        f,
+                        "{}",
+                        SetStubsError::LayoutMismatch {
+                            loader: *loader,
+                            program: *program,
+                        }
+                    ),
+                }
+            }
+        }
+
+        impl std::error::Error for LoadProgramError {}
+
+        /// A program cdylib opened by [`ProgramLibraries`], with the syscall
+        /// table and stub context it was handed. Dropping it unregisters the
+        /// context and closes the library.
+        #[cfg(unix)]
+        pub struct ProgramLibrary {
+            handle: *mut std::ffi::c_void,
+            path: std::path::PathBuf,
+            entrypoint: unsafe extern "C" fn(*mut u8) -> u64,
+            context: StubContextId,
+            _stubs_api: Box<SyscallStubsApi>,
+        }
+
+        #[cfg(unix)]
+        unsafe impl Send for ProgramLibrary {}
+        #[cfg(unix)]
+        unsafe impl Sync for ProgramLibrary {}
+
+        #[cfg(unix)]
+        impl ProgramLibrary {
+            /// Opens the library at `path` with its symbols kept local to it,
+            /// hands it its own `SyscallStubsApi` and registers `stubs` as the
+            /// context its syscalls dispatch to.
+            pub fn open(
+                path: &std::path::Path,
+                stubs: Box<dyn LoaderSyscallStubs>,
+            ) -> Result<Self, LoadProgramError> {
+                use std::os::unix::ffi::OsStrExt;
+                let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+                    .map_err(|_| LoadProgramError::Open("path contains a NUL byte".to_string()))?;
+                let handle = unsafe {
+                    $crate::loader_side::libc::dlopen(
+                        c_path.as_ptr(),
+                        $crate::loader_side::libc::RTLD_NOW | $crate::loader_side::libc::RTLD_LOCAL,
+                    )
+                };
+                if handle.is_null() {
+                    return Err(LoadProgramError::Open(Self::dlerror()));
+                }
+                let close = |error| {
+                    unsafe { $crate::loader_side::libc::dlclose(handle) };
+                    Err(error)
+                };
+                let Some(set_stubs) = (unsafe { Self::symbol(handle, c"set_stubs") }) else {
+                    return close(LoadProgramError::MissingSymbol("set_stubs"));
+                };
+                // `stubs_entrypoint` lets the loader stop the program; see
+                // `invoke_entrypoint`.
+                let Some(entrypoint) = (unsafe { Self::symbol(handle, c"stubs_entrypoint") })
+                    .or_else(|| unsafe { Self::symbol(handle, c"entrypoint") })
+                else {
+                    return close(LoadProgramError::MissingSymbol("entrypoint"));
+                };
+                // Programs built before the fingerprint existed don't export it.
+                if let Some(fingerprint) = unsafe { Self::symbol(handle, c"stubs_abi_fingerprint") } {
+                    let fingerprint: unsafe extern "C" fn() -> u64 =
+                        unsafe { std::mem::transmute(fingerprint) };
+                    let program = unsafe { fingerprint() };
+                    if program != SYSCALL_STUBS_ABI_FINGERPRINT {
+                        return close(LoadProgramError::LayoutMismatch {
+                            loader: SYSCALL_STUBS_ABI_FINGERPRINT,
+                            program,
+                        });
+                    }
+                }
+                let set_stubs: unsafe extern "C" fn(*const SyscallStubsApi) -> u64 =
+                    unsafe { std::mem::transmute(set_stubs) };
+                let entrypoint: unsafe extern "C" fn(*mut u8) -> u64 =
+                    unsafe { std::mem::transmute(entrypoint) };
+                let stubs_api = Box::new(SyscallStubsApi::new());
+                let code = unsafe { set_stubs(&*stubs_api) };
+                if code != SET_STUBS_SUCCESS {
+                    return close(LoadProgramError::SetStubs(code));
+                }
+                Ok(Self {
+                    handle,
+                    path: path.to_path_buf(),
+                    entrypoint,
+                    context: register_stub_context(stubs),
+                    _stubs_api: stubs_api,
+                })
+            }
+
+            unsafe fn symbol(
+                handle: *mut std::ffi::c_void,
+                name: &std::ffi::CStr,
+            ) -> Option<*mut std::ffi::c_void> {
+                let symbol = $crate::loader_side::libc::dlsym(handle, name.as_ptr());
+                (!symbol.is_null()).then_some(symbol)
+            }
+
+            fn dlerror() -> String {
+                let error = unsafe { $crate::loader_side::libc::dlerror() };
+                if error.is_null() {
+                    return "unknown error".to_string();
+                }
+                unsafe { std::ffi::CStr::from_ptr(error) }
+                    .to_string_lossy()
+                    .into_owned()
+            }
+
+            /// The path the library was loaded from.
+            pub fn path(&self) -> &std::path::Path {
+                &self.path
+            }
+
+            /// The stub context the library's syscalls dispatch to.
+            pub fn context(&self) -> StubContextId {
+                self.context
+            }
+
+            /// Runs the program's entrypoint inside its own stub context.
+            ///
+            /// # Safety
+            ///
+            /// `input` must be a serialized input as [`invoke_entrypoint`]
+            /// requires.
+            pub unsafe fn invoke(&self, input: *mut u8) -> Result<u64, ProgramAbort> {
+                let _context = enter_stub_context(self.context);
+                unsafe { invoke_entrypoint(self.entrypoint, input) }
+            }
+        }
+
+        #[cfg(unix)]
+        impl Drop for ProgramLibrary {
+            fn drop(&mut self) {
+                unregister_stub_context(self.context);
+                unsafe { $crate::loader_side::libc::dlclose(self.handle) };
+            }
+        }
+
+        /// Program cdylibs loaded side by side, keyed by program id.
+        ///
+        /// Every library gets its own `SyscallStubsApi`, its own stub context
+        /// and, because libraries are opened with `RTLD_LOCAL`, its own copy of
+        /// solana_program's process-global stubs. A library that is already
+        /// loaded for another program id is copied first so the two instances
+        /// share no state. CPIs between loaded programs go through
+        /// [`ProgramLibraries::invoke`], which switches to the callee's context.
+        #[cfg(unix)]
+        #[derive(Default)]
+        pub struct ProgramLibraries {
+            libraries: std::collections::HashMap<Pubkey, ProgramLibrary>,
+        }
+
+        #[cfg(unix)]
+        impl ProgramLibraries {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            /// Loads the library at `path` as `program_id`, replacing any
+            /// library previously loaded for it once the new one has loaded.
+            /// On error the previous library stays loaded.
+            pub fn load(
+                &mut self,
+                program_id: Pubkey,
+                path: impl AsRef<std::path::Path>,
+                stubs: Box<dyn LoaderSyscallStubs>,
+            ) -> Result<&ProgramLibrary, LoadProgramError> {
+                let path = path.as_ref();
+                // The library being replaced counts too: opening its file again
+                // would hand the new table to the instance still loaded.
+                let library = if self.libraries.values().any(|library| library.path == path) {
+                    let copy = std::env::temp_dir().join(format!(
+                        "{}-{}-{}",
+                        std::process::id(),
+                        program_id,
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ));
+                    std::fs::copy(path, &copy).map_err(LoadProgramError::Copy)?;
+                    let library = ProgramLibrary::open(&copy, stubs);
+                    let _ = std::fs::remove_file(&copy);
+                    let mut library = library?;
+                    library.path = path.to_path_buf();
+                    library
+                } else {
+                    ProgramLibrary::open(path, stubs)?
+                };
+                self.libraries.insert(program_id, library);
+                Ok(&self.libraries[&program_id])
+            }
+
+            /// Unloads the library loaded for `program_id`, returning whether
+            /// there was one.
+            pub fn unload(&mut self, program_id: &Pubkey) -> bool {
+                self.libraries.remove(program_id).is_some()
+            }
+
+            pub fn get(&self, program_id: &Pubkey) -> Option<&ProgramLibrary> {
+                self.libraries.get(program_id)
+            }
+
+            /// Runs the entrypoint of the program loaded for `program_id`, or
+            /// returns `None` if there is none.
+            ///
+            /// # Safety
+            ///
+            /// `input` must be a serialized input as [`invoke_entrypoint`]
+            /// requires.
+            pub unsafe fn invoke(
+                &self,
+                program_id: &Pubkey,
+                input: *mut u8,
+            ) -> Option<Result<u64, ProgramAbort>> {
+                Some(unsafe { self.get(program_id)?.invoke(input) })
+            }
+        }
+
+        /// Compute-unit costs charged by [`MeteredSyscallStubs`]. The defaults
+        /// are the runtime's default compute budget.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct ComputeCosts {
+            pub syscall_base_cost: u64,
+            pub log_64_units: u64,
+            pub log_pubkey_units: u64,
+            pub invoke_units: u64,
+            pub cpi_bytes_per_unit: u64,
+            pub mem_op_base_cost: u64,
+            pub sysvar_base_cost: u64,
+            pub sha256_base_cost: u64,
+            pub sha256_byte_cost: u64,
+            pub create_program_address_units: u64,
+            pub secp256k1_recover_cost: u64,
+            pub curve25519_edwards_validate_point_cost: u64,
+            pub curve25519_edwards_add_cost: u64,
+            pub curve25519_edwards_subtract_cost: u64,
+            pub curve25519_edwards_multiply_cost: u64,
+            pub curve25519_edwards_msm_base_cost: u64,
+            pub curve25519_edwards_msm_incremental_cost: u64,
+            pub curve25519_ristretto_validate_point_cost: u64,
+            pub curve25519_ristretto_add_cost: u64,
+            pub curve25519_ristretto_subtract_cost: u64,
+            pub curve25519_ristretto_multiply_cost: u64,
+            pub curve25519_ristretto_msm_base_cost: u64,
+            pub curve25519_ristretto_msm_incremental_cost: u64,
+            pub alt_bn128_addition_cost: u64,
+            pub alt_bn128_multiplication_cost: u64,
+            pub alt_bn128_pairing_one_pair_cost_first: u64,
+            pub alt_bn128_pairing_one_pair_cost_other: u64,
+            pub alt_bn128_g1_compress: u64,
+            pub alt_bn128_g1_decompress: u64,
+            pub alt_bn128_g2_compress: u64,
+            pub alt_bn128_g2_decompress: u64,
+            pub poseidon_cost_coefficient_a: u64,
+            pub poseidon_cost_coefficient_c: u64,
+            pub big_modular_exponentiation_base_cost: u64,
+            pub big_modular_exponentiation_cost_divisor: u64,
+        }
+
+        impl Default for ComputeCosts {
+            fn default() -> Self {
+                Self {
+                    syscall_base_cost: 100,
+                    log_64_units: 100,
+                    log_pubkey_units: 100,
+                    invoke_units: 1_000,
+                    cpi_bytes_per_unit: 250,
+                    mem_op_base_cost: 10,
+                    sysvar_base_cost: 100,
+                    sha256_base_cost: 85,
+                    sha256_byte_cost: 1,
+                    create_program_address_units: 1_500,
+                    secp256k1_recover_cost: 25_000,
+                    curve25519_edwards_validate_point_cost: 159,
+                    curve25519_edwards_add_cost: 473,
+                    curve25519_edwards_subtract_cost: 475,
+                    curve25519_edwards_multiply_cost: 2_177,
+                    curve25519_edwards_msm_base_cost: 2_273,
+                    curve25519_edwards_msm_incremental_cost: 758,
+                    curve25519_ristretto_validate_point_cost: 169,
+                    curve25519_ristretto_add_cost: 521,
+                    curve25519_ristretto_subtract_cost: 519,
+                    curve25519_ristretto_multiply_cost: 2_208,
+                    curve25519_ristretto_msm_base_cost: 2_303,
+                    curve25519_ristretto_msm_incremental_cost: 788,
+                    alt_bn128_addition_cost: 334,
+                    alt_bn128_multiplication_cost: 3_840,
+                    alt_bn128_pairing_one_pair_cost_first: 36_364,
+                    alt_bn128_pairing_one_pair_cost_other: 12_121,
+                    alt_bn128_g1_compress: 30,
+                    alt_bn128_g1_decompress: 398,
+                    alt_bn128_g2_compress: 86,
+                    alt_bn128_g2_decompress: 13_610,
+                    poseidon_cost_coefficient_a: 61,
+                    poseidon_cost_coefficient_c: 542,
+                    big_modular_exponentiation_base_cost: 190,
+                    big_modular_exponentiation_cost_divisor: 2,
+                }
+            }
+        }
+
+        /// Compute units an instruction may consume unless configured otherwise.
+        pub const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+        /// Charges every syscall against a compute budget before forwarding it
+        /// to the wrapped stubs, following the runtime's cost model.
+        ///
+        /// When a syscall costs more than is left, it fails without reaching the
+        /// wrapped stubs and the program is stopped with
+        /// [`ProgramAbort::ComputeBudgetExceeded`] once the syscall returns.
+        /// Only syscalls are metered: the program's own instructions are free.
+        ///
+        /// Programs reached through CPI share the budget as long as their
+        /// syscalls go through the same `MeteredSyscallStubs`.
+        pub struct MeteredSyscallStubs {
+            inner: Box<dyn LoaderSyscallStubs>,
+            costs: ComputeCosts,
+            budget: std::sync::atomic::AtomicU64,
+            remaining: std::sync::atomic::AtomicU64,
+        }
+
+        impl MeteredSyscallStubs {
+            pub fn new(inner: Box<dyn LoaderSyscallStubs>) -> Self {
+                Self {
+                    inner,
+                    costs: ComputeCosts::default(),
+                    budget: std::sync::atomic::AtomicU64::new(DEFAULT_COMPUTE_UNIT_LIMIT),
+                    remaining: std::sync::atomic::AtomicU64::new(DEFAULT_COMPUTE_UNIT_LIMIT),
+                }
+            }
+
+            pub fn with_budget(self, budget: u64) -> Self {
+                self.set_budget(budget);
+                self
+            }
+
+            pub fn with_costs(mut self, costs: ComputeCosts) -> Self {
+                self.costs = costs;
+                self
+            }
+
+            /// Sets the budget and refills it, as at the start of an instruction.
+            pub fn set_budget(&self, budget: u64) {
+                self.budget
+                    .store(budget, std::sync::atomic::Ordering::SeqCst);
+                self.reset();
+            }
+
+            /// Refills the budget for the next instruction.
+            pub fn reset(&self) {
+                self.remaining.store(
+                    self.budget.load(std::sync::atomic::Ordering::SeqCst),
+                    std::sync::atomic::Ordering::SeqCst,
+                );
+            }
+
+            pub fn budget(&self) -> u64 {
+                self.budget.load(std::sync::atomic::Ordering::SeqCst)
+            }
+
+            pub fn remaining(&self) -> u64 {
+                self.remaining.load(std::sync::atomic::Ordering::SeqCst)
+            }
+
+            pub fn consumed(&self) -> u64 {
+                self.budget() - self.remaining()
+            }
+
+            pub fn costs(&self) -> &ComputeCosts {
+                &self.costs
+            }
+
+            /// Charges `units`. When the budget can't cover them, empties it,
+            /// requests [`ProgramAbort::ComputeBudgetExceeded`] and returns
+            /// `false`: the syscall must then fail without running.
+            #[must_use]
+            pub fn consume(&self, units: u64) -> bool {
+                let charged = self.remaining.fetch_update(
+                    std::sync::atomic::Ordering::SeqCst,
+                    std::sync::atomic::Ordering::SeqCst,
+                    |remaining| remaining.checked_sub(units),
+                );
+                if charged.is_err() {
+                    self.remaining
+                        .store(0, std::sync::atomic::Ordering::SeqCst);
+                    let budget = self.budget();
+                    request_program_abort(ProgramAbort::ComputeBudgetExceeded { budget });
+                }
+                charged.is_ok()
+            }
+
+            fn consume_mem_op(&self, n: u64) -> bool {
+                self.consume(
+                    self.costs
+                        .mem_op_base_cost
+                        .max(n / self.costs.cpi_bytes_per_unit),
+                )
+            }
+
+            fn consume_hash(&self, vals: &[&[u8]]) -> bool {
+                let costs = &self.costs;
+                self.consume(costs.sha256_base_cost)
+                    && vals.iter().all(|val| {
+                        self.consume(
+                            costs
+                                .mem_op_base_cost
+                                .max(costs.sha256_byte_cost.saturating_mul(val.len() as u64 / 2)),
+                        )
+                    })
+            }
+
+            fn consume_sysvar(&self, size: u64) -> bool {
+                self.consume(self.costs.sysvar_base_cost.saturating_add(size))
+            }
+        }
+
+        impl SyscallStubs for MeteredSyscallStubs {
+            fn sol_log(&self, message: &str) {
+                if !self.consume(self.costs.syscall_base_cost.max(message.len() as u64)) {
+                    return;
+                }
+                self.inner.sol_log(message);
+            }
+            fn sol_log_compute_units(&self) {
+                if !self.consume(self.costs.syscall_base_cost) {
+                    return;
+                }
+                self.inner.sol_log(&format!(
+                    "Program consumption: {} units remaining",
+                    self.remaining()
+                ));
+            }
+            fn sol_remaining_compute_units(&self) -> u64 {
+                if !self.consume(self.costs.syscall_base_cost) {
+                    return SYSCALL_FAILURE;
+                }
+                self.remaining()
+            }
+            fn sol_invoke_signed(
+                &self,
+                instruction: &Instruction,
+                account_infos: &[AccountInfo],
+                signers_seeds: &[&[&[u8]]],
+            ) -> ProgramResult {
+                let bytes = account_infos
+                    .iter()
+                    .map(|account_info| account_info.data_len() as u64)
+                    .fold(instruction.data.len() as u64, u64::saturating_add);
+                if !self.consume(
+                    self.costs
+                        .invoke_units
+                        .saturating_add(bytes / self.costs.cpi_bytes_per_unit),
+                ) {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                self.inner
+                    .sol_invoke_signed(instruction, account_infos, signers_seeds)
+            }
+            fn sol_get_sysvar(
+                &self,
+                sysvar_id_addr: *const u8,
+                var_addr: *mut u8,
+                offset: u64,
+                length: u64,
+            ) -> u64 {
+                let costs = &self.costs;
+                if !self.consume(
+                    costs
+                        .sysvar_base_cost
+                        .saturating_add(PUBKEY_BYTES as u64 / costs.cpi_bytes_per_unit)
+                        .saturating_add(
+                            (length / costs.cpi_bytes_per_unit).max(costs.mem_op_base_cost),
+                        ),
+                ) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_get_sysvar(sysvar_id_addr, var_addr, offset, length)
+            }
+            fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(CLOCK_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_clock_sysvar(var_addr)
+            }
+            fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(EPOCH_SCHEDULE_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_epoch_schedule_sysvar(var_addr)
+            }
+            fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(FEES_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_fees_sysvar(var_addr)
+            }
+            fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(RENT_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_rent_sysvar(var_addr)
+            }
+            fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(EPOCH_REWARDS_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_epoch_rewards_sysvar(var_addr)
+            }
+            fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+                if !self.consume_sysvar(LAST_RESTART_SLOT_SYSVAR_SIZE as u64) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_last_restart_slot(var_addr)
+            }
+            fn sol_get_epoch_stake(&self, vote_address: *const u8) -> u64 {
+                let mut units = self.costs.syscall_base_cost;
+                if !vote_address.is_null() {
+                    units = units.saturating_add(PUBKEY_BYTES as u64 / self.costs.cpi_bytes_per_unit);
+                }
+                if !self.consume(units) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_epoch_stake(vote_address)
+            }
+            unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+                if !self.consume_mem_op(n as u64) {
+                    return;
+                }
+                self.inner.sol_memcpy(dst, src, n)
+            }
+            unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+                if !self.consume_mem_op(n as u64) {
+                    return;
+                }
+                self.inner.sol_memmove(dst, src, n)
+            }
+            unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+                if !self.consume_mem_op(n as u64) {
+                    return;
+                }
+                self.inner.sol_memcmp(s1, s2, n, result)
+            }
+            unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+                if !self.consume_mem_op(n as u64) {
+                    return;
+                }
+                self.inner.sol_memset(s, c, n)
+            }
+            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                if !self.consume(self.costs.syscall_base_cost) {
+                    return None;
+                }
+                let return_data = self.inner.sol_get_return_data();
+                if let Some((_, data)) = &return_data {
+                    if !self.consume(
+                        (data.len() as u64 + PUBKEY_BYTES as u64) / self.costs.cpi_bytes_per_unit,
+                    ) {
+                        return None;
+                    }
+                }
+                return_data
+            }
+            fn sol_set_return_data(&self, data: &[u8]) {
+                if !self.consume(
+                    self.costs
+                        .syscall_base_cost
+                        .saturating_add(data.len() as u64 / self.costs.cpi_bytes_per_unit),
+                ) {
+                    return;
+                }
+                self.inner.sol_set_return_data(data)
+            }
+            fn sol_log_data(&self, fields: &[&[u8]]) {
+                let costs = &self.costs;
+                if !self.consume(costs.syscall_base_cost) {
+                    return;
+                }
+                if !self.consume(costs.syscall_base_cost.saturating_mul(fields.len() as u64)) {
+                    return;
+                }
+                if !self.consume(
+                    fields
+                        .iter()
+                        .map(|field| field.len() as u64)
+                        .fold(0, u64::saturating_add),
+                ) {
+                    return;
+                }
+                self.inner.sol_log_data(fields)
+            }
+            fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+                if !self.consume(self.costs.syscall_base_cost) {
+                    return None;
+                }
+                self.inner.sol_get_processed_sibling_instruction(index)
+            }
+            fn sol_get_stack_height(&self) -> u64 {
+                if !self.consume(self.costs.syscall_base_cost) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_get_stack_height()
+            }
+        }
+
+        impl LoaderSyscallStubs for MeteredSyscallStubs {
+            fn sol_log_64(&self, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+                if !self.consume(self.costs.log_64_units) {
+                    return;
+                }
+                self.inner.sol_log_64(arg1, arg2, arg3, arg4, arg5)
+            }
+            fn sol_log_pubkey(&self, pubkey: &Pubkey) {
+                if !self.consume(self.costs.log_pubkey_units) {
+                    return;
+                }
+                self.inner.sol_log_pubkey(pubkey)
+            }
+            fn sol_panic(&self, file: &str, line: u64, column: u64, message: &str) {
+                self.inner.sol_panic(file, line, column, message)
+            }
+            fn sol_sha256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                if !self.consume_hash(vals) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_sha256(vals, hash_result)
+            }
+            fn sol_keccak256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                if !self.consume_hash(vals) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_keccak256(vals, hash_result)
+            }
+            fn sol_blake3(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                if !self.consume_hash(vals) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_blake3(vals, hash_result)
+            }
+            fn sol_create_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+            ) -> u64 {
+                if !self.consume(self.costs.create_program_address_units) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_create_program_address(seeds, program_id, address)
+            }
+            fn sol_try_find_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+                bump_seed: &mut u8,
+            ) -> u64 {
+                // The runtime tries every bump counting down from 255 and
+                // charges each attempt before making it, so a search the budget
+                // can't pay for stops where the runtime's would.
+                for bump in (1..=u8::MAX).rev() {
+                    if !self.consume(self.costs.create_program_address_units) {
+                        return SYSCALL_FAILURE;
+                    }
+                    let bump_slice = [bump];
+                    let seeds_with_bump: Vec<&[u8]> =
+                        seeds.iter().copied().chain([&bump_slice[..]]).collect();
+                    let result =
+                        self.inner
+                            .sol_create_program_address(&seeds_with_bump, program_id, address);
+                    if result == 0 {
+                        *bump_seed = bump;
+                        return 0;
+                    }
+                }
+                SYSCALL_FAILURE
+            }
+            fn sol_secp256k1_recover(
+                &self,
+                hash: &[u8; HASH_BYTES],
+                recovery_id: u8,
+                signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                if !self.consume(self.costs.secp256k1_recover_cost) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_secp256k1_recover(hash, recovery_id, signature, result)
+            }
+            fn sol_curve_validate_point(
+                &self,
+                curve_id: u64,
+                point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let costs = &self.costs;
+                if !self.consume(match curve_id {
+                    CURVE25519_EDWARDS => costs.curve25519_edwards_validate_point_cost,
+                    CURVE25519_RISTRETTO => costs.curve25519_ristretto_validate_point_cost,
+                    _ => 0,
+                }) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_curve_validate_point(curve_id, point)
+            }
+            fn sol_curve_group_op(
+                &self,
+                curve_id: u64,
+                group_op: u64,
+                left_input: &[u8; CURVE25519_POINT_BYTES],
+                right_input: &[u8; CURVE25519_POINT_BYTES],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let costs = &self.costs;
+                if !self.consume(match (curve_id, group_op) {
+                    (CURVE25519_EDWARDS, CURVE25519_ADD) => costs.curve25519_edwards_add_cost,
+                    (CURVE25519_EDWARDS, CURVE25519_SUB) => costs.curve25519_edwards_subtract_cost,
+                    (CURVE25519_EDWARDS, CURVE25519_MUL) => costs.curve25519_edwards_multiply_cost,
+                    (CURVE25519_RISTRETTO, CURVE25519_ADD) => costs.curve25519_ristretto_add_cost,
+                    (CURVE25519_RISTRETTO, CURVE25519_SUB) => {
+                        costs.curve25519_ristretto_subtract_cost
+                    }
+                    (CURVE25519_RISTRETTO, CURVE25519_MUL) => {
+                        costs.curve25519_ristretto_multiply_cost
+                    }
+                    _ => 0,
+                }) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_curve_group_op(curve_id, group_op, left_input, right_input, result_point)
+            }
+            fn sol_curve_multiscalar_mul(
+                &self,
+                curve_id: u64,
+                scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                points: &[[u8; CURVE25519_POINT_BYTES]],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let costs = &self.costs;
+                let (base, incremental) = match curve_id {
+                    CURVE25519_EDWARDS => (
+                        costs.curve25519_edwards_msm_base_cost,
+                        costs.curve25519_edwards_msm_incremental_cost,
+                    ),
+                    CURVE25519_RISTRETTO => (
+                        costs.curve25519_ristretto_msm_base_cost,
+                        costs.curve25519_ristretto_msm_incremental_cost,
+                    ),
+                    _ => (0, 0),
+                };
+                if !self.consume(base.saturating_add(
+                    incremental.saturating_mul((points.len() as u64).saturating_sub(1)),
+                )) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_curve_multiscalar_mul(curve_id, scalars, points, result_point)
+            }
+            fn sol_alt_bn128_group_op(&self, group_op: u64, input: &[u8], result: &mut [u8]) -> u64 {
+                let costs = &self.costs;
+                let units = match group_op {
+                    ALT_BN128_ADD | ALT_BN128_SUB => costs.alt_bn128_addition_cost,
+                    ALT_BN128_MUL => costs.alt_bn128_multiplication_cost,
+                    ALT_BN128_PAIRING => {
+                        // Pairings are charged per 192-byte (G1, G2) pair.
+                        let pairs = input.len() as u64 / 192;
+                        costs
+                            .alt_bn128_pairing_one_pair_cost_other
+                            .saturating_mul(pairs.saturating_sub(1))
+                            .saturating_add(costs.alt_bn128_pairing_one_pair_cost_first)
+                            .saturating_add(costs.sha256_base_cost)
+                            .saturating_add(input.len() as u64)
+                            .saturating_add(result.len() as u64)
+                    }
+                    _ => 0,
+                };
+                if !self.consume(units) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_alt_bn128_group_op(group_op, input, result)
+            }
+            fn sol_alt_bn128_compression(&self, op: u64, input: &[u8], result: &mut [u8]) -> u64 {
+                let costs = &self.costs;
+                if !self.consume(costs.syscall_base_cost.saturating_add(match op {
+                    ALT_BN128_G1_COMPRESS => costs.alt_bn128_g1_compress,
+                    ALT_BN128_G1_DECOMPRESS => costs.alt_bn128_g1_decompress,
+                    ALT_BN128_G2_COMPRESS => costs.alt_bn128_g2_compress,
+                    ALT_BN128_G2_DECOMPRESS => costs.alt_bn128_g2_decompress,
+                    _ => 0,
+                })) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_alt_bn128_compression(op, input, result)
+            }
+            fn sol_poseidon(
+                &self,
+                parameters: u64,
+                endianness: u64,
+                vals: &[&[u8]],
+                hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                let costs = &self.costs;
+                let inputs = vals.len() as u64;
+                if !self.consume(
+                    costs
+                        .poseidon_cost_coefficient_a
+                        .saturating_mul(inputs.saturating_mul(inputs))
+                        .saturating_add(costs.poseidon_cost_coefficient_c),
+                ) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner
+                    .sol_poseidon(parameters, endianness, vals, hash_result)
+            }
+            fn sol_big_mod_exp(
+                &self,
+                base: &[u8],
+                exponent: &[u8],
+                modulus: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                let costs = &self.costs;
+                let input_len = base.len().max(exponent.len()).max(modulus.len()) as u64;
+                if !self.consume(
+                    costs.big_modular_exponentiation_base_cost.saturating_add(
+                        input_len.saturating_mul(input_len)
+                            / costs.big_modular_exponentiation_cost_divisor,
+                    ),
+                ) {
+                    return SYSCALL_FAILURE;
+                }
+                self.inner.sol_big_mod_exp(base, exponent, modulus, result)
+            }
+            fn sol_clear_return_data(&self) {
+                // Part of the CPI, which is charged on its own.
+                self.inner.sol_clear_return_data()
+            }
+        }
+
+        /// Where [`TracingSyscallStubs`] puts its records.
+        enum TraceSink {
+            Memory(Vec<$crate::loader_side::serde_json::Value>),
+            Writer(Box<dyn std::io::Write + Send>),
+        }
+
+        /// Records every syscall before handing back the wrapped stubs' result.
+        ///
+        /// Each record is one JSON object:
+        /// `{"seq", "depth", "syscall", "args", "result", "output"}`. `seq`
+        /// numbers calls in the order the program made them and `depth` counts
+        /// the CPIs on the calling thread they are nested in, since a CPI is
+        /// recorded after the calls its callee made. `result` is the syscall's return code, if it has
+        /// one, and `output` holds whatever it wrote back to the program. Byte
+        /// strings are hex and pubkeys base58.
+        pub struct TracingSyscallStubs {
+            inner: Box<dyn LoaderSyscallStubs>,
+            sink: std::sync::Mutex<TraceSink>,
+            seq: std::sync::atomic::AtomicU64,
+            /// CPI depth of each thread with a CPI in progress.
+            depths: std::sync::Mutex<std::collections::HashMap<std::thread::ThreadId, u64>>,
+        }
+
+        /// Counts a CPI traced by [`TracingSyscallStubs`] on the calling thread
+        /// until dropped.
+        struct TraceCpiGuard<'a>(&'a TracingSyscallStubs);
+
+        impl Drop for TraceCpiGuard<'_> {
+            fn drop(&mut self) {
+                let mut depths = self.0.depths.lock().unwrap();
+                let thread = std::thread::current().id();
+                if let Some(depth) = depths.get_mut(&thread) {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        depths.remove(&thread);
+                    }
+                }
+            }
+        }
+
+        impl TracingSyscallStubs {
+            /// Keeps the trace in memory, see [`TracingSyscallStubs::records`].
+            pub fn new(inner: Box<dyn LoaderSyscallStubs>) -> Self {
+                Self::with_sink(inner, TraceSink::Memory(Vec::new()))
+            }
+
+            /// Writes the trace as JSON Lines to `writer`.
+            pub fn to_writer(
+                inner: Box<dyn LoaderSyscallStubs>,
+                writer: Box<dyn std::io::Write + Send>,
+            ) -> Self {
+                Self::with_sink(inner, TraceSink::Writer(writer))
+            }
+
+            /// Writes the trace as JSON Lines to a new file at `path`.
+            pub fn to_file(
+                inner: Box<dyn LoaderSyscallStubs>,
+                path: impl AsRef<std::path::Path>,
+            ) -> std::io::Result<Self> {
+                let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                Ok(Self::to_writer(inner, Box::new(file)))
+            }
+
+            fn with_sink(inner: Box<dyn LoaderSyscallStubs>, sink: TraceSink) -> Self {
+                Self {
+                    inner,
+                    sink: std::sync::Mutex::new(sink),
+                    seq: std::sync::atomic::AtomicU64::new(0),
+                    depths: std::sync::Mutex::new(std::collections::HashMap::new()),
+                }
+            }
+
+            /// The records kept so far, empty when tracing to a writer.
+            pub fn records(&self) -> Vec<$crate::loader_side::serde_json::Value> {
+                match &*self.sink.lock().unwrap() {
+                    TraceSink::Memory(records) => records.clone(),
+                    TraceSink::Writer(_) => Vec::new(),
+                }
+            }
+
+            /// Flushes the writer the trace goes to.
+            pub fn flush(&self) -> std::io::Result<()> {
+                match &mut *self.sink.lock().unwrap() {
+                    TraceSink::Memory(_) => Ok(()),
+                    TraceSink::Writer(writer) => writer.flush(),
+                }
+            }
+
+            fn next_seq(&self) -> u64 {
+                self.seq.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            }
+
+            /// The number of CPIs the calling thread is in.
+            fn depth(&self) -> u64 {
+                let depths = self.depths.lock().unwrap();
+                depths.get(&std::thread::current().id()).copied().unwrap_or(0)
+            }
+
+            fn enter_cpi(&self) -> TraceCpiGuard<'_> {
+                let mut depths = self.depths.lock().unwrap();
+                *depths.entry(std::thread::current().id()).or_insert(0) += 1;
+                TraceCpiGuard(self)
+            }
+
+            fn record(
+                &self,
+                seq: u64,
+                syscall: &str,
+                args: $crate::loader_side::serde_json::Value,
+                result: Option<u64>,
+                output: $crate::loader_side::serde_json::Value,
+            ) {
+                let record = $crate::loader_side::serde_json::json!({
+                    "seq": seq,
+                    "depth": self.depth(),
+                    "syscall": syscall,
+                    "args": args,
+                    "result": result,
+                    "output": output,
+                });
+                match &mut *self.sink.lock().unwrap() {
+                    TraceSink::Memory(records) => records.push(record),
+                    TraceSink::Writer(writer) => {
+                        // A trace is a debugging aid; failing to write one
+                        // mustn't fail the program.
+                        let _ = writeln!(writer, "{record}");
+                    }
+                }
+            }
+
+            /// Records a call that has no arguments worth decoding and returns nothing.
+            fn record_plain(&self, seq: u64, syscall: &str, result: Option<u64>) {
+                self.record(
+                    seq,
+                    syscall,
+                    $crate::loader_side::serde_json::json!({}),
+                    result,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+
+            fn record_sysvar(
+                &self,
+                seq: u64,
+                syscall: &str,
+                var_addr: *mut u8,
+                size: usize,
+                result: u64,
+            ) {
+                self.record(
+                    seq,
+                    syscall,
+                    $crate::loader_side::serde_json::json!({}),
+                    Some(result),
+                    Self::trace_written(var_addr, size, result),
+                );
+            }
+
+            /// `{"data": hex}` of the `size` bytes at `addr` if the call succeeded.
+            fn trace_written(
+                addr: *const u8,
+                size: usize,
+                result: u64,
+            ) -> $crate::loader_side::serde_json::Value {
+                if result != 0 || addr.is_null() {
+                    return $crate::loader_side::serde_json::Value::Null;
+                }
+                let data = unsafe { std::slice::from_raw_parts(addr, size) };
+                $crate::loader_side::serde_json::json!({ "data": Self::trace_bytes(data) })
+            }
+
+            fn trace_bytes(data: &[u8]) -> $crate::loader_side::serde_json::Value {
+                $crate::loader_side::hex_encode(data).into()
+            }
+
+            fn trace_slices(slices: &[&[u8]]) -> $crate::loader_side::serde_json::Value {
+                slices.iter().map(|slice| Self::trace_bytes(slice)).collect()
+            }
+
+            fn trace_pubkey(pubkey: &Pubkey) -> $crate::loader_side::serde_json::Value {
+                pubkey.to_string().into()
+            }
+
+            fn trace_instruction(instruction: &Instruction) -> $crate::loader_side::serde_json::Value {
+                let accounts: Vec<_> = instruction
+                    .accounts
+                    .iter()
+                    .map(|meta| {
+                        $crate::loader_side::serde_json::json!({
+                            "pubkey": Self::trace_pubkey(&meta.pubkey),
+                            "is_signer": meta.is_signer,
+                            "is_writable": meta.is_writable,
+                        })
+                    })
+                    .collect();
+                $crate::loader_side::serde_json::json!({
+                    "program_id": Self::trace_pubkey(&instruction.program_id),
+                    "accounts": accounts,
+                    "data": Self::trace_bytes(&instruction.data),
+                })
+            }
+
+            fn trace_account(account_info: &AccountInfo) -> $crate::loader_side::serde_json::Value {
+                $crate::loader_side::serde_json::json!({
+                    "key": Self::trace_pubkey(account_info.key),
+                    "lamports": account_info.lamports(),
+                    "owner": Self::trace_pubkey(account_info.owner),
+                    "data": Self::trace_bytes(&account_info.data.borrow()),
+                    "executable": account_info.executable,
+                    "is_signer": account_info.is_signer,
+                    "is_writable": account_info.is_writable,
+                })
+            }
+        }
+
+        impl SyscallStubs for TracingSyscallStubs {
+            fn sol_log(&self, message: &str) {
+                let seq = self.next_seq();
+                self.inner.sol_log(message);
+                self.record(
+                    seq,
+                    "sol_log",
+                    $crate::loader_side::serde_json::json!({ "message": message }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_log_compute_units(&self) {
+                let seq = self.next_seq();
+                self.inner.sol_log_compute_units();
+                self.record_plain(seq, "sol_log_compute_units", None);
+            }
+            fn sol_remaining_compute_units(&self) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_remaining_compute_units();
+                self.record_plain(seq, "sol_remaining_compute_units", Some(result));
+                result
+            }
+            fn sol_invoke_signed(
+                &self,
+                instruction: &Instruction,
+                account_infos: &[AccountInfo],
+                signers_seeds: &[&[&[u8]]],
+            ) -> ProgramResult {
+                let seq = self.next_seq();
+                let args = $crate::loader_side::serde_json::json!({
+                    "instruction": Self::trace_instruction(instruction),
+                    "account_infos": account_infos.iter().map(Self::trace_account).collect::<Vec<_>>(),
+                    "signers_seeds": signers_seeds
+                        .iter()
+                        .map(|seeds| Self::trace_slices(seeds))
+                        .collect::<Vec<_>>(),
+                });
+                let cpi = self.enter_cpi();
+                let result = self
+                    .inner
+                    .sol_invoke_signed(instruction, account_infos, signers_seeds);
+                drop(cpi);
+                let output = $crate::loader_side::serde_json::json!({
+                    "account_infos": account_infos.iter().map(Self::trace_account).collect::<Vec<_>>(),
+                });
+                self.record(
+                    seq,
+                    "sol_invoke_signed",
+                    args,
+                    Some(program_result_to_u64(&result)),
+                    output,
+                );
+                result
+            }
+            fn sol_get_sysvar(
+                &self,
+                sysvar_id_addr: *const u8,
+                var_addr: *mut u8,
+                offset: u64,
+                length: u64,
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self
+                    .inner
+                    .sol_get_sysvar(sysvar_id_addr, var_addr, offset, length);
+                let sysvar_id = Pubkey::new_from_array(unsafe { *(sysvar_id_addr as *const [u8; 32]) });
+                self.record(
+                    seq,
+                    "sol_get_sysvar",
+                    $crate::loader_side::serde_json::json!({
+                        "sysvar_id": Self::trace_pubkey(&sysvar_id),
+                        "offset": offset,
+                        "length": length,
+                    }),
+                    Some(result),
+                    Self::trace_written(var_addr, length as usize, result),
+                );
+                result
+            }
+            fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_clock_sysvar(var_addr);
+                self.record_sysvar(
+                    seq,
+                    "sol_get_clock_sysvar",
+                    var_addr,
+                    CLOCK_SYSVAR_SIZE,
+                    result,
+                );
+                result
+            }
+            fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_epoch_schedule_sysvar(var_addr);
+                self.record_sysvar(
+                    seq,
+                    "sol_get_epoch_schedule_sysvar",
+                    var_addr,
+                    EPOCH_SCHEDULE_SYSVAR_SIZE,
+                    result,
+                );
+                result
+            }
+            fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_fees_sysvar(var_addr);
+                self.record_sysvar(seq, "sol_get_fees_sysvar", var_addr, FEES_SYSVAR_SIZE, result);
+                result
+            }
+            fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_rent_sysvar(var_addr);
+                self.record_sysvar(seq, "sol_get_rent_sysvar", var_addr, RENT_SYSVAR_SIZE, result);
+                result
+            }
+            fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_epoch_rewards_sysvar(var_addr);
+                self.record_sysvar(
+                    seq,
+                    "sol_get_epoch_rewards_sysvar",
+                    var_addr,
+                    EPOCH_REWARDS_SYSVAR_SIZE,
+                    result,
+                );
+                result
+            }
+            fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_last_restart_slot(var_addr);
+                self.record_sysvar(
+                    seq,
+                    "sol_get_last_restart_slot",
+                    var_addr,
+                    LAST_RESTART_SLOT_SYSVAR_SIZE,
+                    result,
+                );
+                result
+            }
+            fn sol_get_epoch_stake(&self, vote_address: *const u8) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_epoch_stake(vote_address);
+                let vote_address = (!vote_address.is_null()).then(|| {
+                    Self::trace_pubkey(&Pubkey::new_from_array(unsafe {
+                        *(vote_address as *const [u8; 32])
+                    }))
+                });
+                self.record(
+                    seq,
+                    "sol_get_epoch_stake",
+                    $crate::loader_side::serde_json::json!({ "vote_address": vote_address }),
+                    Some(result),
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+                result
+            }
+            unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+                let seq = self.next_seq();
+                self.inner.sol_memcpy(dst, src, n);
+                self.record(
+                    seq,
+                    "sol_memcpy",
+                    $crate::loader_side::serde_json::json!({ "n": n }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+                let seq = self.next_seq();
+                self.inner.sol_memmove(dst, src, n);
+                self.record(
+                    seq,
+                    "sol_memmove",
+                    $crate::loader_side::serde_json::json!({ "n": n }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+                let seq = self.next_seq();
+                self.inner.sol_memcmp(s1, s2, n, result);
+                self.record(
+                    seq,
+                    "sol_memcmp",
+                    $crate::loader_side::serde_json::json!({ "n": n }),
+                    None,
+                    $crate::loader_side::serde_json::json!({ "result": *result }),
+                );
+            }
+            unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+                let seq = self.next_seq();
+                self.inner.sol_memset(s, c, n);
+                self.record(
+                    seq,
+                    "sol_memset",
+                    $crate::loader_side::serde_json::json!({ "c": c, "n": n }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                let seq = self.next_seq();
+                let return_data = self.inner.sol_get_return_data();
+                let output = match &return_data {
+                    Some((program_id, data)) => $crate::loader_side::serde_json::json!({
+                        "program_id": Self::trace_pubkey(program_id),
+                        "data": Self::trace_bytes(data),
+                    }),
+                    None => $crate::loader_side::serde_json::Value::Null,
+                };
+                self.record(
+                    seq,
+                    "sol_get_return_data",
+                    $crate::loader_side::serde_json::json!({}),
+                    None,
+                    output,
+                );
+                return_data
+            }
+            fn sol_set_return_data(&self, data: &[u8]) {
+                let seq = self.next_seq();
+                self.inner.sol_set_return_data(data);
+                self.record(
+                    seq,
+                    "sol_set_return_data",
+                    $crate::loader_side::serde_json::json!({ "data": Self::trace_bytes(data) }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_log_data(&self, fields: &[&[u8]]) {
+                let seq = self.next_seq();
+                self.inner.sol_log_data(fields);
+                self.record(
+                    seq,
+                    "sol_log_data",
+                    $crate::loader_side::serde_json::json!({ "fields": Self::trace_slices(fields) }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
+                let seq = self.next_seq();
+                let instruction = self.inner.sol_get_processed_sibling_instruction(index);
+                self.record(
+                    seq,
+                    "sol_get_processed_sibling_instruction",
+                    $crate::loader_side::serde_json::json!({ "index": index }),
+                    None,
+                    instruction
+                        .as_ref()
+                        .map_or($crate::loader_side::serde_json::Value::Null, |instruction| {
+                            $crate::loader_side::serde_json::json!({
+                                "instruction": Self::trace_instruction(instruction),
+                            })
+                        }),
+                );
+                instruction
+            }
+            fn sol_get_stack_height(&self) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_get_stack_height();
+                self.record_plain(seq, "sol_get_stack_height", Some(result));
+                result
+            }
+        }
+
+        impl LoaderSyscallStubs for TracingSyscallStubs {
+            fn sol_log_64(&self, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+                let seq = self.next_seq();
+                self.inner.sol_log_64(arg1, arg2, arg3, arg4, arg5);
+                self.record(
+                    seq,
+                    "sol_log_64_",
+                    $crate::loader_side::serde_json::json!({
+                        "args": [arg1, arg2, arg3, arg4, arg5],
+                    }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_log_pubkey(&self, pubkey: &Pubkey) {
+                let seq = self.next_seq();
+                self.inner.sol_log_pubkey(pubkey);
+                self.record(
+                    seq,
+                    "sol_log_pubkey",
+                    $crate::loader_side::serde_json::json!({ "pubkey": Self::trace_pubkey(pubkey) }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+            }
+            fn sol_panic(&self, file: &str, line: u64, column: u64, message: &str) {
+                let seq = self.next_seq();
+                self.inner.sol_panic(file, line, column, message);
+                self.record(
+                    seq,
+                    "sol_panic_",
+                    $crate::loader_side::serde_json::json!({
+                        "file": file,
+                        "line": line,
+                        "column": column,
+                        "message": message,
+                    }),
+                    None,
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+                // The program is stopped right after this; don't lose the record.
+                let _ = self.flush();
+            }
+            fn sol_sha256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_sha256(vals, hash_result);
+                self.record(
+                    seq,
+                    "sol_sha256",
+                    $crate::loader_side::serde_json::json!({ "vals": Self::trace_slices(vals) }),
+                    Some(result),
+                    Self::trace_written(hash_result.as_ptr(), HASH_BYTES, result),
+                );
+                result
+            }
+            fn sol_keccak256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_keccak256(vals, hash_result);
+                self.record(
+                    seq,
+                    "sol_keccak256",
+                    $crate::loader_side::serde_json::json!({ "vals": Self::trace_slices(vals) }),
+                    Some(result),
+                    Self::trace_written(hash_result.as_ptr(), HASH_BYTES, result),
+                );
+                result
+            }
+            fn sol_blake3(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_blake3(vals, hash_result);
+                self.record(
+                    seq,
+                    "sol_blake3",
+                    $crate::loader_side::serde_json::json!({ "vals": Self::trace_slices(vals) }),
+                    Some(result),
+                    Self::trace_written(hash_result.as_ptr(), HASH_BYTES, result),
+                );
+                result
+            }
+            fn sol_create_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self
+                    .inner
+                    .sol_create_program_address(seeds, program_id, address);
+                self.record(
+                    seq,
+                    "sol_create_program_address",
+                    $crate::loader_side::serde_json::json!({
+                        "seeds": Self::trace_slices(seeds),
+                        "program_id": Self::trace_pubkey(program_id),
+                    }),
+                    Some(result),
+                    if result == 0 {
+                        $crate::loader_side::serde_json::json!({
+                            "address": Self::trace_pubkey(address),
+                        })
+                    } else {
+                        $crate::loader_side::serde_json::Value::Null
+                    },
+                );
+                result
+            }
+            fn sol_try_find_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+                bump_seed: &mut u8,
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self
+                    .inner
+                    .sol_try_find_program_address(seeds, program_id, address, bump_seed);
+                self.record(
+                    seq,
+                    "sol_try_find_program_address",
+                    $crate::loader_side::serde_json::json!({
+                        "seeds": Self::trace_slices(seeds),
+                        "program_id": Self::trace_pubkey(program_id),
+                    }),
+                    Some(result),
+                    if result == 0 {
+                        $crate::loader_side::serde_json::json!({
+                            "address": Self::trace_pubkey(address),
+                            "bump_seed": *bump_seed,
+                        })
+                    } else {
+                        $crate::loader_side::serde_json::Value::Null
+                    },
+                );
+                result
             }
-            fn sol_get_epoch_schedule_sysvar(&self, _var_addr: *mut u8) -> u64 {
-                unimplemented!()
+            fn sol_secp256k1_recover(
+                &self,
+                hash: &[u8; HASH_BYTES],
+                recovery_id: u8,
+                signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let code = self
+                    .inner
+                    .sol_secp256k1_recover(hash, recovery_id, signature, result);
+                self.record(
+                    seq,
+                    "sol_secp256k1_recover",
+                    $crate::loader_side::serde_json::json!({
+                        "hash": Self::trace_bytes(hash),
+                        "recovery_id": recovery_id,
+                        "signature": Self::trace_bytes(signature),
+                    }),
+                    Some(code),
+                    Self::trace_written(result.as_ptr(), SECP256K1_PUBLIC_KEY_LENGTH, code),
+                );
+                code
             }
-            fn sol_get_fees_sysvar(&self, _var_addr: *mut u8) -> u64 {
-                unimplemented!()
+            fn sol_curve_validate_point(
+                &self,
+                curve_id: u64,
+                point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_curve_validate_point(curve_id, point);
+                self.record(
+                    seq,
+                    "sol_curve_validate_point",
+                    $crate::loader_side::serde_json::json!({
+                        "curve_id": curve_id,
+                        "point": Self::trace_bytes(point),
+                    }),
+                    Some(result),
+                    $crate::loader_side::serde_json::Value::Null,
+                );
+                result
             }
-            fn sol_get_last_restart_slot(&self, _var_addr: *mut u8) -> u64 {
-                unimplemented!()
+            fn sol_curve_group_op(
+                &self,
+                curve_id: u64,
+                group_op: u64,
+                left_input: &[u8; CURVE25519_POINT_BYTES],
+                right_input: &[u8; CURVE25519_POINT_BYTES],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self.inner.sol_curve_group_op(
+                    curve_id,
+                    group_op,
+                    left_input,
+                    right_input,
+                    result_point,
+                );
+                self.record(
+                    seq,
+                    "sol_curve_group_op",
+                    $crate::loader_side::serde_json::json!({
+                        "curve_id": curve_id,
+                        "group_op": group_op,
+                        "left_input": Self::trace_bytes(left_input),
+                        "right_input": Self::trace_bytes(right_input),
+                    }),
+                    Some(result),
+                    Self::trace_written(result_point.as_ptr(), CURVE25519_POINT_BYTES, result),
+                );
+                result
             }
-            fn sol_get_processed_sibling_instruction(&self, _index: usize) -> Option<Instruction> {
-                unimplemented!()
+            fn sol_curve_multiscalar_mul(
+                &self,
+                curve_id: u64,
+                scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                points: &[[u8; CURVE25519_POINT_BYTES]],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self
+                    .inner
+                    .sol_curve_multiscalar_mul(curve_id, scalars, points, result_point);
+                self.record(
+                    seq,
+                    "sol_curve_multiscalar_mul",
+                    $crate::loader_side::serde_json::json!({
+                        "curve_id": curve_id,
+                        "scalars": scalars.iter().map(|scalar| Self::trace_bytes(scalar)).collect::<Vec<_>>(),
+                        "points": points.iter().map(|point| Self::trace_bytes(point)).collect::<Vec<_>>(),
+                    }),
+                    Some(result),
+                    Self::trace_written(result_point.as_ptr(), CURVE25519_POINT_BYTES, result),
+                );
+                result
             }
-            fn sol_get_rent_sysvar(&self, _var_addr: *mut u8) -> u64 {
-                unimplemented!()
+            fn sol_alt_bn128_group_op(&self, group_op: u64, input: &[u8], result: &mut [u8]) -> u64 {
+                let seq = self.next_seq();
+                let code = self.inner.sol_alt_bn128_group_op(group_op, input, result);
+                self.record(
+                    seq,
+                    "sol_alt_bn128_group_op",
+                    $crate::loader_side::serde_json::json!({
+                        "group_op": group_op,
+                        "input": Self::trace_bytes(input),
+                    }),
+                    Some(code),
+                    Self::trace_written(result.as_ptr(), result.len(), code),
+                );
+                code
             }
-            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
-                unimplemented!()
+            fn sol_alt_bn128_compression(&self, op: u64, input: &[u8], result: &mut [u8]) -> u64 {
+                let seq = self.next_seq();
+                let code = self.inner.sol_alt_bn128_compression(op, input, result);
+                self.record(
+                    seq,
+                    "sol_alt_bn128_compression",
+                    $crate::loader_side::serde_json::json!({
+                        "op": op,
+                        "input": Self::trace_bytes(input),
+                    }),
+                    Some(code),
+                    Self::trace_written(result.as_ptr(), result.len(), code),
+                );
+                code
             }
-            fn sol_get_stack_height(&self) -> u64 {
-                unimplemented!()
+            fn sol_poseidon(
+                &self,
+                parameters: u64,
+                endianness: u64,
+                vals: &[&[u8]],
+                hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let result = self
+                    .inner
+                    .sol_poseidon(parameters, endianness, vals, hash_result);
+                self.record(
+                    seq,
+                    "sol_poseidon",
+                    $crate::loader_side::serde_json::json!({
+                        "parameters": parameters,
+                        "endianness": endianness,
+                        "vals": Self::trace_slices(vals),
+                    }),
+                    Some(result),
+                    Self::trace_written(hash_result.as_ptr(), POSEIDON_HASH_BYTES, result),
+                );
+                result
+            }
+            fn sol_big_mod_exp(
+                &self,
+                base: &[u8],
+                exponent: &[u8],
+                modulus: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                let seq = self.next_seq();
+                let code = self.inner.sol_big_mod_exp(base, exponent, modulus, result);
+                self.record(
+                    seq,
+                    "sol_big_mod_exp",
+                    $crate::loader_side::serde_json::json!({
+                        "base": Self::trace_bytes(base),
+                        "exponent": Self::trace_bytes(exponent),
+                        "modulus": Self::trace_bytes(modulus),
+                    }),
+                    Some(code),
+                    Self::trace_written(result.as_ptr(), result.len(), code),
+                );
+                code
+            }
+            fn sol_clear_return_data(&self) {
+                // Implied by the `sol_invoke_signed` record.
+                self.inner.sol_clear_return_data()
+            }
+        }
+
+        /// Plays a trace recorded by [`TracingSyscallStubs`] back to the program.
+        ///
+        /// Every syscall takes the next top-level record (`depth` 0, in `seq`
+        /// order) and hands back what was recorded: return codes, sysvar bytes,
+        /// hashes, CPI results and the account changes they made, return data
+        /// and sibling instructions. Memory syscalls are still performed, since
+        /// they only touch the program's own memory, and logs are printed.
+        ///
+        /// Only the syscall names are checked against the trace. If the program
+        /// makes a different syscall than was recorded, or runs past the end of
+        /// the trace, the syscall fails and the program is stopped with
+        /// [`ProgramAbort::ReplayDiverged`] once it returns.
+        pub struct ReplaySyscallStubs {
+            records: std::sync::Mutex<std::collections::VecDeque<$crate::loader_side::serde_json::Value>>,
+        }
+
+        impl ReplaySyscallStubs {
+            pub fn new(records: Vec<$crate::loader_side::serde_json::Value>) -> Self {
+                let mut records: Vec<_> = records
+                    .into_iter()
+                    .filter(|record| record["depth"].as_u64() == Some(0))
+                    .collect();
+                records.sort_by_key(|record| record["seq"].as_u64());
+                Self {
+                    records: std::sync::Mutex::new(records.into()),
+                }
+            }
+
+            /// Reads a JSON Lines trace written by [`TracingSyscallStubs::to_file`].
+            pub fn from_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+                let trace = std::fs::read_to_string(path)?;
+                let records = trace
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map($crate::loader_side::serde_json::from_str)
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+                Ok(Self::new(records))
+            }
+
+            /// How many records the program hasn't replayed yet.
+            pub fn remaining(&self) -> usize {
+                self.records.lock().unwrap().len()
+            }
+
+            /// Runs `replay`, failing the syscall with `failure` and stopping the
+            /// program if it has diverged from the trace.
+            fn replay<T>(&self, failure: T, replay: impl FnOnce() -> Result<T, String>) -> T {
+                replay().unwrap_or_else(|reason| {
+                    request_program_abort(ProgramAbort::ReplayDiverged(reason));
+                    failure
+                })
+            }
+
+            fn next(&self, syscall: &str) -> Result<$crate::loader_side::serde_json::Value, String> {
+                let Some(record) = self.records.lock().unwrap().pop_front() else {
+                    return Err(format!("replay: {syscall} was called after the trace ended"));
+                };
+                if record["syscall"] != syscall {
+                    return Err(format!(
+                        "replay: {syscall} was called where the trace has {} (seq {})",
+                        record["syscall"], record["seq"]
+                    ));
+                }
+                Ok(record)
+            }
+
+            fn result(&self, record: &$crate::loader_side::serde_json::Value) -> Result<u64, String> {
+                record["result"]
+                    .as_u64()
+                    .ok_or_else(|| format!("replay: record {} has no result", record["seq"]))
+            }
+
+            fn bytes(&self, value: &$crate::loader_side::serde_json::Value) -> Result<Vec<u8>, String> {
+                value
+                    .as_str()
+                    .and_then($crate::loader_side::hex_decode)
+                    .ok_or_else(|| format!("replay: {value} is not a hex byte string"))
+            }
+
+            fn pubkey(&self, value: &$crate::loader_side::serde_json::Value) -> Result<Pubkey, String> {
+                value
+                    .as_str()
+                    .and_then(|pubkey| <Pubkey as std::str::FromStr>::from_str(pubkey).ok())
+                    .ok_or_else(|| format!("replay: {value} is not a pubkey"))
+            }
+
+            fn instruction(&self, value: &$crate::loader_side::serde_json::Value) -> Result<Instruction, String> {
+                let accounts = value["accounts"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|meta| {
+                        Ok(AccountMeta {
+                            pubkey: self.pubkey(&meta["pubkey"])?,
+                            is_signer: meta["is_signer"].as_bool().unwrap_or_default(),
+                            is_writable: meta["is_writable"].as_bool().unwrap_or_default(),
+                        })
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(Instruction {
+                    program_id: self.pubkey(&value["program_id"])?,
+                    accounts,
+                    data: self.bytes(&value["data"])?,
+                })
+            }
+
+            /// Copies the recorded `output.data` to `addr` and returns the
+            /// recorded result, checking that it is `len` bytes long.
+            fn write_output(
+                &self,
+                record: &$crate::loader_side::serde_json::Value,
+                addr: *mut u8,
+                len: usize,
+            ) -> Result<u64, String> {
+                let result = self.result(record)?;
+                if result == 0 {
+                    let data = self.bytes(&record["output"]["data"])?;
+                    if data.len() != len {
+                        return Err(format!(
+                            "replay: record {} holds {} bytes where {len} are expected",
+                            record["seq"],
+                            data.len()
+                        ));
+                    }
+                    unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), addr, len) };
+                }
+                Ok(result)
+            }
+
+            /// Replays a syscall that writes `len` bytes to `addr` and returns a code.
+            fn replay_output(&self, syscall: &str, addr: *mut u8, len: usize) -> u64 {
+                self.replay(SYSCALL_FAILURE, || {
+                    let record = self.next(syscall)?;
+                    self.write_output(&record, addr, len)
+                })
+            }
+
+            /// Replays a syscall that only returns a code.
+            fn replay_result(&self, syscall: &str) -> u64 {
+                self.replay(SYSCALL_FAILURE, || self.result(&self.next(syscall)?))
+            }
+
+            /// Replays a syscall that returns nothing.
+            fn replay_call(&self, syscall: &str) -> Result<(), String> {
+                self.next(syscall).map(drop)
+            }
+
+            fn replay_invoke(&self, account_infos: &[AccountInfo]) -> Result<ProgramResult, String> {
+                let record = self.next("sol_invoke_signed")?;
+                let result = self.result(&record)?;
+                let recorded = record["output"]["account_infos"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                if recorded.len() != account_infos.len() {
+                    return Err(format!(
+                        "replay: record {} has {} accounts where {} were passed",
+                        record["seq"],
+                        recorded.len(),
+                        account_infos.len()
+                    ));
+                }
+                let changes = recorded
+                    .iter()
+                    .map(|recorded| {
+                        Ok((
+                            recorded["lamports"].as_u64(),
+                            self.pubkey(&recorded["owner"])?,
+                            self.bytes(&recorded["data"])?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                for (account_info, (lamports, owner, data)) in account_infos.iter().zip(changes) {
+                    if let Some(lamports) = lamports {
+                        **account_info.lamports.borrow_mut() = lamports;
+                    }
+                    if *account_info.owner != owner {
+                        account_info.assign(&owner);
+                    }
+                    let mut account_data = account_info.data.borrow_mut();
+                    if account_data.len() >= data.len() {
+                        let buffer = std::mem::take(&mut *account_data);
+                        let (kept, _) = buffer.split_at_mut(data.len());
+                        kept.copy_from_slice(&data);
+                        *account_data = kept;
+                    } else {
+                        // The loader copies resized data back into the caller's
+                        // buffer once the CPI returns, so the grown data only
+                        // has to outlive the instruction.
+                        let grown = current_instruction_arena().alloc_data(data);
+                        *account_data = unsafe { &mut *grown };
+                    }
+                }
+                Ok(program_result_from_u64(result))
+            }
+        }
+
+        impl SyscallStubs for ReplaySyscallStubs {
+            fn sol_log(&self, message: &str) {
+                self.replay((), || {
+                    self.replay_call("sol_log")?;
+                    eprintln!("Program log: {message}");
+                    Ok(())
+                })
+            }
+            fn sol_log_compute_units(&self) {
+                self.replay((), || self.replay_call("sol_log_compute_units"))
+            }
+            fn sol_remaining_compute_units(&self) -> u64 {
+                self.replay(0, || self.result(&self.next("sol_remaining_compute_units")?))
             }
             fn sol_invoke_signed(
                 &self,
                 _instruction: &Instruction,
-                _account_infos: &[AccountInfo],
+                account_infos: &[AccountInfo],
                 _signers_seeds: &[&[&[u8]]],
             ) -> ProgramResult {
-                unimplemented!()
+                self.replay(Err(ProgramError::InvalidArgument), || {
+                    self.replay_invoke(account_infos)
+                })
             }
-            fn sol_log(&self, _message: &str) {
-                unimplemented!()
+            fn sol_get_sysvar(
+                &self,
+                _sysvar_id_addr: *const u8,
+                var_addr: *mut u8,
+                _offset: u64,
+                length: u64,
+            ) -> u64 {
+                self.replay_output("sol_get_sysvar", var_addr, length as usize)
             }
-            fn sol_log_compute_units(&self) {
-                unimplemented!()
+            fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output("sol_get_clock_sysvar", var_addr, CLOCK_SYSVAR_SIZE)
             }
-            fn sol_log_data(&self, _fields: &[&[u8]]) {
-                unimplemented!()
+            fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output(
+                    "sol_get_epoch_schedule_sysvar",
+                    var_addr,
+                    EPOCH_SCHEDULE_SYSVAR_SIZE,
+                )
             }
-            unsafe fn sol_memcmp(
-                &self,
-                _s1: *const u8,
-                _s2: *const u8,
-                _n: usize,
-                _result: *mut i32,
-            ) {
-                unimplemented!()
+            fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output("sol_get_fees_sysvar", var_addr, FEES_SYSVAR_SIZE)
             }
-            unsafe fn sol_memcpy(&self, _dst: *mut u8, _src: *const u8, _n: usize) {
-                unimplemented!()
+            fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output("sol_get_rent_sysvar", var_addr, RENT_SYSVAR_SIZE)
             }
-            unsafe fn sol_memmove(&self, _dst: *mut u8, _src: *const u8, _n: usize) {
-                unimplemented!()
+            fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output(
+                    "sol_get_epoch_rewards_sysvar",
+                    var_addr,
+                    EPOCH_REWARDS_SYSVAR_SIZE,
+                )
             }
-            unsafe fn sol_memset(&self, _s: *mut u8, _c: u8, _n: usize) {
-                unimplemented!()
+            fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+                self.replay_output(
+                    "sol_get_last_restart_slot",
+                    var_addr,
+                    LAST_RESTART_SLOT_SYSVAR_SIZE,
+                )
             }
-            fn sol_remaining_compute_units(&self) -> u64 {
-                unimplemented!()
+            fn sol_get_epoch_stake(&self, _vote_address: *const u8) -> u64 {
+                self.replay(0, || self.result(&self.next("sol_get_epoch_stake")?))
+            }
+            unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
+                self.replay((), || {
+                    self.replay_call("sol_memcpy")?;
+                    std::ptr::copy_nonoverlapping(src, dst, n);
+                    Ok(())
+                })
+            }
+            unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
+                self.replay((), || {
+                    self.replay_call("sol_memmove")?;
+                    std::ptr::copy(src, dst, n);
+                    Ok(())
+                })
+            }
+            unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
+                self.replay((), || {
+                    self.replay_call("sol_memcmp")?;
+                    let s1 = std::slice::from_raw_parts(s1, n);
+                    let s2 = std::slice::from_raw_parts(s2, n);
+                    *result = s1
+                        .iter()
+                        .zip(s2)
+                        .find(|(a, b)| a != b)
+                        .map_or(0, |(a, b)| *a as i32 - *b as i32);
+                    Ok(())
+                })
+            }
+            unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
+                self.replay((), || {
+                    self.replay_call("sol_memset")?;
+                    std::ptr::write_bytes(s, c, n);
+                    Ok(())
+                })
+            }
+            fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                self.replay(None, || {
+                    let record = self.next("sol_get_return_data")?;
+                    let output = &record["output"];
+                    if output.is_null() {
+                        return Ok(None);
+                    }
+                    Ok(Some((
+                        self.pubkey(&output["program_id"])?,
+                        self.bytes(&output["data"])?,
+                    )))
+                })
             }
             fn sol_set_return_data(&self, _data: &[u8]) {
-                unimplemented!()
+                self.replay((), || self.replay_call("sol_set_return_data"))
+            }
+            fn sol_log_data(&self, fields: &[&[u8]]) {
+                self.replay((), || {
+                    self.replay_call("sol_log_data")?;
+                    let fields: Vec<_> = fields
+                        .iter()
+                        .map(|field| $crate::loader_side::base64_encode(field))
+                        .collect();
+                    eprintln!("Program data: {}", fields.join(" "));
+                    Ok(())
+                })
+            }
+            fn sol_get_processed_sibling_instruction(&self, _index: usize) -> Option<Instruction> {
+                self.replay(None, || {
+                    let record = self.next("sol_get_processed_sibling_instruction")?;
+                    let output = &record["output"];
+                    if output.is_null() {
+                        return Ok(None);
+                    }
+                    self.instruction(&output["instruction"]).map(Some)
+                })
+            }
+            fn sol_get_stack_height(&self) -> u64 {
+                self.replay(0, || self.result(&self.next("sol_get_stack_height")?))
+            }
+        }
+
+        impl LoaderSyscallStubs for ReplaySyscallStubs {
+            fn sol_log_64(&self, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+                self.replay((), || {
+                    self.replay_call("sol_log_64_")?;
+                    eprintln!("Program log: {arg1:#x}, {arg2:#x}, {arg3:#x}, {arg4:#x}, {arg5:#x}");
+                    Ok(())
+                })
+            }
+            fn sol_log_pubkey(&self, pubkey: &Pubkey) {
+                self.replay((), || {
+                    self.replay_call("sol_log_pubkey")?;
+                    eprintln!("Program log: {pubkey}");
+                    Ok(())
+                })
+            }
+            fn sol_panic(&self, _file: &str, _line: u64, _column: u64, _message: &str) {
+                self.replay((), || self.replay_call("sol_panic_"))
+            }
+            fn sol_sha256(&self, _vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.replay_output("sol_sha256", hash_result.as_mut_ptr(), HASH_BYTES)
+            }
+            fn sol_keccak256(&self, _vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.replay_output("sol_keccak256", hash_result.as_mut_ptr(), HASH_BYTES)
+            }
+            fn sol_blake3(&self, _vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.replay_output("sol_blake3", hash_result.as_mut_ptr(), HASH_BYTES)
+            }
+            fn sol_create_program_address(
+                &self,
+                _seeds: &[&[u8]],
+                _program_id: &Pubkey,
+                address: &mut Pubkey,
+            ) -> u64 {
+                self.replay(SYSCALL_FAILURE, || {
+                    let record = self.next("sol_create_program_address")?;
+                    let result = self.result(&record)?;
+                    if result == 0 {
+                        *address = self.pubkey(&record["output"]["address"])?;
+                    }
+                    Ok(result)
+                })
+            }
+            fn sol_try_find_program_address(
+                &self,
+                _seeds: &[&[u8]],
+                _program_id: &Pubkey,
+                address: &mut Pubkey,
+                bump_seed: &mut u8,
+            ) -> u64 {
+                self.replay(SYSCALL_FAILURE, || {
+                    let record = self.next("sol_try_find_program_address")?;
+                    let result = self.result(&record)?;
+                    if result == 0 {
+                        *address = self.pubkey(&record["output"]["address"])?;
+                        *bump_seed =
+                            record["output"]["bump_seed"].as_u64().unwrap_or_default() as u8;
+                    }
+                    Ok(result)
+                })
+            }
+            fn sol_secp256k1_recover(
+                &self,
+                _hash: &[u8; HASH_BYTES],
+                _recovery_id: u8,
+                _signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                self.replay_output(
+                    "sol_secp256k1_recover",
+                    result.as_mut_ptr(),
+                    SECP256K1_PUBLIC_KEY_LENGTH,
+                )
+            }
+            fn sol_curve_validate_point(
+                &self,
+                _curve_id: u64,
+                _point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.replay_result("sol_curve_validate_point")
+            }
+            fn sol_curve_group_op(
+                &self,
+                _curve_id: u64,
+                _group_op: u64,
+                _left_input: &[u8; CURVE25519_POINT_BYTES],
+                _right_input: &[u8; CURVE25519_POINT_BYTES],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.replay_output(
+                    "sol_curve_group_op",
+                    result_point.as_mut_ptr(),
+                    CURVE25519_POINT_BYTES,
+                )
+            }
+            fn sol_curve_multiscalar_mul(
+                &self,
+                _curve_id: u64,
+                _scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                _points: &[[u8; CURVE25519_POINT_BYTES]],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.replay_output(
+                    "sol_curve_multiscalar_mul",
+                    result_point.as_mut_ptr(),
+                    CURVE25519_POINT_BYTES,
+                )
+            }
+            fn sol_alt_bn128_group_op(&self, _group_op: u64, _input: &[u8], result: &mut [u8]) -> u64 {
+                self.replay_output("sol_alt_bn128_group_op", result.as_mut_ptr(), result.len())
+            }
+            fn sol_alt_bn128_compression(&self, _op: u64, _input: &[u8], result: &mut [u8]) -> u64 {
+                self.replay_output("sol_alt_bn128_compression", result.as_mut_ptr(), result.len())
+            }
+            fn sol_poseidon(
+                &self,
+                _parameters: u64,
+                _endianness: u64,
+                _vals: &[&[u8]],
+                hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                self.replay_output("sol_poseidon", hash_result.as_mut_ptr(), POSEIDON_HASH_BYTES)
+            }
+            fn sol_big_mod_exp(
+                &self,
+                _base: &[u8],
+                _exponent: &[u8],
+                _modulus: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                self.replay_output("sol_big_mod_exp", result.as_mut_ptr(), result.len())
             }
+            fn sol_clear_return_data(&self) {
+                // Return data is replayed from the `sol_get_return_data` records.
+            }
+        }
+
+        /// Reinterprets `len` (ptr, len) pairs at `addr` as byte slices, the way
+        /// `&[&[u8]]` is laid out by the caller.
+        unsafe fn fat_ptr_slices<'a>(addr: *const u8, len: u64) -> Vec<&'a [u8]> {
+            let fat_ptrs = addr as *const (*const u8, u64);
+            (0..len)
+                .map(|i| {
+                    let (data_ptr, data_len) = *fat_ptrs.add(i as _);
+                    std::slice::from_raw_parts(data_ptr, data_len as _)
+                })
+                .collect()
         }
 
+        // The exported syscalls stand in for the runtime's, so they keep the safe
+        // `extern "C" fn` signatures that programs declare and `SyscallStubsApi`
+        // stores. Like the runtime, they trust the program to pass valid pointers
+        // and lengths.
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_log_(msg: *const u8, len: u64) {
             let message = unsafe { std::slice::from_raw_parts(msg, len as _) };
             let m = String::from_utf8_lossy(message);
-            SYSCALL_STUBS.read().unwrap().sol_log(&m);
+            current_syscall_stubs().sol_log(&m);
+        }
+
+        #[no_mangle]
+        pub extern "C" fn sol_log_64_(arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+            current_syscall_stubs()
+                .sol_log_64(arg1, arg2, arg3, arg4, arg5);
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_log_pubkey(pubkey_addr: *const u8) {
+            let pubkey = unsafe { &*(pubkey_addr as *const Pubkey) };
+            current_syscall_stubs().sol_log_pubkey(pubkey);
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_panic_(
+            file: *const u8,
+            file_len: u64,
+            line: u64,
+            column: u64,
+            message: *const u8,
+            message_len: u64,
+        ) {
+            let (file, message) = unsafe {
+                (
+                    std::slice::from_raw_parts(file, file_len as _),
+                    std::slice::from_raw_parts(message, message_len as _),
+                )
+            };
+            let panic = ProgramPanic {
+                file: String::from_utf8_lossy(file).into_owned(),
+                line,
+                column,
+                message: String::from_utf8_lossy(message).into_owned(),
+            };
+            current_syscall_stubs()
+                .sol_panic(&panic.file, line, column, &panic.message);
+            abort_program(panic.into());
         }
 
         #[no_mangle]
         pub extern "C" fn sol_log_compute_units_() {
-            SYSCALL_STUBS.read().unwrap().sol_log_compute_units();
+            current_syscall_stubs().sol_log_compute_units();
         }
 
         #[no_mangle]
         pub extern "C" fn sol_remaining_compute_units() -> u64 {
-            SYSCALL_STUBS.read().unwrap().sol_remaining_compute_units()
+            current_syscall_stubs().sol_remaining_compute_units()
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_memcpy_(dst: *mut u8, src: *const u8, n: u64) {
             unsafe {
-                SYSCALL_STUBS.read().unwrap().sol_memcpy(dst, src, n as _);
+                current_syscall_stubs().sol_memcpy(dst, src, n as _);
             }
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_memmove_(dst: *mut u8, src: *const u8, n: u64) {
             unsafe {
-                SYSCALL_STUBS.read().unwrap().sol_memmove(dst, src, n as _);
+                current_syscall_stubs().sol_memmove(dst, src, n as _);
             }
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_memcmp_(s1: *const u8, s2: *const u8, n: u64, result: *mut i32) {
             unsafe {
-                SYSCALL_STUBS
-                    .read()
-                    .unwrap()
+                current_syscall_stubs()
                     .sol_memcmp(s1, s2, n as _, result);
             }
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_memset_(s: *mut u8, c: u8, n: u64) {
             unsafe {
-                SYSCALL_STUBS.read().unwrap().sol_memset(s, c, n as _);
+                current_syscall_stubs().sol_memset(s, c, n as _);
             }
         }
 
         #[no_mangle]
         pub extern "C" fn sol_get_stack_height() -> u64 {
-            SYSCALL_STUBS.read().unwrap().sol_get_stack_height()
+            current_syscall_stubs().sol_get_stack_height()
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_clock_sysvar(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS.read().unwrap().sol_get_clock_sysvar(addr)
+            current_syscall_stubs().sol_get_clock_sysvar(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_epoch_schedule_sysvar(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS
-                .read()
-                .unwrap()
+            current_syscall_stubs()
                 .sol_get_epoch_schedule_sysvar(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_fees_sysvar(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS.read().unwrap().sol_get_fees_sysvar(addr)
+            current_syscall_stubs().sol_get_fees_sysvar(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_rent_sysvar(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS.read().unwrap().sol_get_rent_sysvar(addr)
+            current_syscall_stubs().sol_get_rent_sysvar(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_epoch_rewards_sysvar(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS
-                .read()
-                .unwrap()
+            current_syscall_stubs()
                 .sol_get_epoch_rewards_sysvar(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_last_restart_slot(addr: *mut u8) -> u64 {
-            SYSCALL_STUBS
-                .read()
-                .unwrap()
+            current_syscall_stubs()
                 .sol_get_last_restart_slot(addr)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_epoch_stake(vote_address: *const u8) -> u64 {
-            SYSCALL_STUBS
-                .read()
-                .unwrap()
+            current_syscall_stubs()
                 .sol_get_epoch_stake(vote_address)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_sysvar(
             sysvar_id_addr: *const u8,
             result: *mut u8,
             offset: u64,
             length: u64,
         ) -> u64 {
-            SYSCALL_STUBS
-                .read()
-                .unwrap()
+            current_syscall_stubs()
                 .sol_get_sysvar(sysvar_id_addr, result, offset, length)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_set_return_data(data: *const u8, length: u64) {
+            // The runtime aborts the program rather than truncating.
+            if length > MAX_RETURN_DATA as u64 {
+                abort_program(ProgramAbort::ReturnDataTooLarge { len: length });
+                return;
+            }
             let slice = unsafe { std::slice::from_raw_parts(data, length as _) };
-            SYSCALL_STUBS.read().unwrap().sol_set_return_data(slice);
+            current_syscall_stubs().sol_set_return_data(slice);
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_return_data(
             data: *mut u8,
             length: u64,
             program_id: *mut CPubkey,
         ) -> u64 {
-            let ret_data = SYSCALL_STUBS.read().unwrap().sol_get_return_data();
-
-            match ret_data {
-                None => 0,
-                Some((key, src)) => {
-                    // Caller is wondering how many to allocate.
-                    if length == 0 {
-                        unsafe { *program_id = key.as_array().into() };
-                        return src.len() as _;
-                    }
-
-                    // Caller is ready with the allocation - we're expected to copy the data.
-                    // Let's check if there's enough space.
-                    let src_len = src.len() as _;
-                    if src_len > length || unsafe { *(*program_id).as_array() } != *key.as_array() {
-                        return 0;
-                    }
-                    unsafe {
-                        std::ptr::copy_nonoverlapping(src.as_ptr(), data, length as _);
-                    };
-                    src_len
+            let Some((key, src)) = current_syscall_stubs().sol_get_return_data() else {
+                return 0;
+            };
+            // Like the runtime: copy what fits in the caller's buffer, and return
+            // the full length so the caller can tell it was truncated.
+            let copy_len = std::cmp::min(length, src.len() as u64);
+            if copy_len != 0 {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(src.as_ptr(), data, copy_len as _);
+                    *program_id = key.as_array().into();
                 }
             }
+            src.len() as _
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_log_data(data: *const u8, data_len: u64) {
-            // reinterpret the buffer as a fat pointer to (*const u8, usize) pairs
-            let fat_ptrs = data as *const (*const u8, u64);
-            let mut v: Vec<&[u8]> = Vec::with_capacity(data_len as _);
-            for i in 0..data_len {
-                let (data_ptr, len) = unsafe { *fat_ptrs.add(i as _) };
-                let slice = unsafe { std::slice::from_raw_parts(data_ptr, len as _) };
-                v.push(slice);
+            let v = unsafe { fat_ptr_slices(data, data_len) };
+            current_syscall_stubs().sol_log_data(&v[..]);
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_sha256(vals: *const u8, val_len: u64, hash_result: *mut u8) -> u64 {
+            let vals = unsafe { fat_ptr_slices(vals, val_len) };
+            let hash_result = unsafe { &mut *(hash_result as *mut [u8; HASH_BYTES]) };
+            current_syscall_stubs()
+                .sol_sha256(&vals[..], hash_result)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_keccak256(
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            let vals = unsafe { fat_ptr_slices(vals, val_len) };
+            let hash_result = unsafe { &mut *(hash_result as *mut [u8; HASH_BYTES]) };
+            current_syscall_stubs()
+                .sol_keccak256(&vals[..], hash_result)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_blake3(vals: *const u8, val_len: u64, hash_result: *mut u8) -> u64 {
+            let vals = unsafe { fat_ptr_slices(vals, val_len) };
+            let hash_result = unsafe { &mut *(hash_result as *mut [u8; HASH_BYTES]) };
+            current_syscall_stubs()
+                .sol_blake3(&vals[..], hash_result)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_create_program_address(
+            seeds_addr: *const u8,
+            seeds_len: u64,
+            program_id_addr: *const u8,
+            address_bytes_addr: *mut u8,
+        ) -> u64 {
+            let seeds = unsafe { fat_ptr_slices(seeds_addr, seeds_len) };
+            let program_id = unsafe { &*(program_id_addr as *const Pubkey) };
+            let address = unsafe { &mut *(address_bytes_addr as *mut Pubkey) };
+            current_syscall_stubs().sol_create_program_address(
+                &seeds[..],
+                program_id,
+                address,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_try_find_program_address(
+            seeds_addr: *const u8,
+            seeds_len: u64,
+            program_id_addr: *const u8,
+            address_bytes_addr: *mut u8,
+            bump_seed_addr: *mut u8,
+        ) -> u64 {
+            let seeds = unsafe { fat_ptr_slices(seeds_addr, seeds_len) };
+            let program_id = unsafe { &*(program_id_addr as *const Pubkey) };
+            let address = unsafe { &mut *(address_bytes_addr as *mut Pubkey) };
+            let bump_seed = unsafe { &mut *bump_seed_addr };
+            current_syscall_stubs().sol_try_find_program_address(
+                &seeds[..],
+                program_id,
+                address,
+                bump_seed,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_secp256k1_recover(
+            hash: *const u8,
+            recovery_id: u64,
+            signature: *const u8,
+            result: *mut u8,
+        ) -> u64 {
+            // Like the runtime, reject recovery ids that don't fit in a byte
+            // before the implementation gets to see them.
+            let Ok(recovery_id) = u8::try_from(recovery_id) else {
+                return SECP256K1_RECOVER_ERROR_INVALID_RECOVERY_ID;
+            };
+            let hash = unsafe { &*(hash as *const [u8; HASH_BYTES]) };
+            let signature = unsafe { &*(signature as *const [u8; SECP256K1_SIGNATURE_LENGTH]) };
+            let result = unsafe { &mut *(result as *mut [u8; SECP256K1_PUBLIC_KEY_LENGTH]) };
+            current_syscall_stubs().sol_secp256k1_recover(
+                hash,
+                recovery_id,
+                signature,
+                result,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_curve_validate_point(
+            curve_id: u64,
+            point_addr: *const u8,
+            _result: *mut u8,
+        ) -> u64 {
+            let point = unsafe { &*(point_addr as *const [u8; CURVE25519_POINT_BYTES]) };
+            current_syscall_stubs()
+                .sol_curve_validate_point(curve_id, point)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_curve_group_op(
+            curve_id: u64,
+            group_op: u64,
+            left_input_addr: *const u8,
+            right_input_addr: *const u8,
+            result_point_addr: *mut u8,
+        ) -> u64 {
+            let left_input = unsafe { &*(left_input_addr as *const [u8; CURVE25519_POINT_BYTES]) };
+            let right_input =
+                unsafe { &*(right_input_addr as *const [u8; CURVE25519_POINT_BYTES]) };
+            let result_point =
+                unsafe { &mut *(result_point_addr as *mut [u8; CURVE25519_POINT_BYTES]) };
+            current_syscall_stubs().sol_curve_group_op(
+                curve_id,
+                group_op,
+                left_input,
+                right_input,
+                result_point,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_curve_multiscalar_mul(
+            curve_id: u64,
+            scalars_addr: *const u8,
+            points_addr: *const u8,
+            points_len: u64,
+            result_point_addr: *mut u8,
+        ) -> u64 {
+            if points_len > CURVE25519_MAX_MULTISCALAR_POINTS {
+                return SYSCALL_FAILURE;
+            }
+            let scalars = unsafe {
+                std::slice::from_raw_parts(
+                    scalars_addr as *const [u8; CURVE25519_SCALAR_BYTES],
+                    points_len as _,
+                )
+            };
+            let points = unsafe {
+                std::slice::from_raw_parts(
+                    points_addr as *const [u8; CURVE25519_POINT_BYTES],
+                    points_len as _,
+                )
+            };
+            let result_point =
+                unsafe { &mut *(result_point_addr as *mut [u8; CURVE25519_POINT_BYTES]) };
+            current_syscall_stubs().sol_curve_multiscalar_mul(
+                curve_id,
+                scalars,
+                points,
+                result_point,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_alt_bn128_group_op(
+            group_op: u64,
+            input: *const u8,
+            input_size: u64,
+            result: *mut u8,
+        ) -> u64 {
+            // The runtime rejects unknown operations before touching any buffer.
+            let Some(result_len) = alt_bn128_group_op_result_len(group_op) else {
+                return SYSCALL_FAILURE;
+            };
+            let input = unsafe { std::slice::from_raw_parts(input, input_size as _) };
+            let result = unsafe { std::slice::from_raw_parts_mut(result, result_len) };
+            current_syscall_stubs()
+                .sol_alt_bn128_group_op(group_op, input, result)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_alt_bn128_compression(
+            op: u64,
+            input: *const u8,
+            input_size: u64,
+            result: *mut u8,
+        ) -> u64 {
+            let Some(result_len) = alt_bn128_compression_result_len(op) else {
+                return SYSCALL_FAILURE;
+            };
+            let input = unsafe { std::slice::from_raw_parts(input, input_size as _) };
+            let result = unsafe { std::slice::from_raw_parts_mut(result, result_len) };
+            current_syscall_stubs()
+                .sol_alt_bn128_compression(op, input, result)
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_poseidon(
+            parameters: u64,
+            endianness: u64,
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            if val_len > POSEIDON_MAX_INPUTS {
+                return SYSCALL_FAILURE;
+            }
+            let vals = unsafe { fat_ptr_slices(vals, val_len) };
+            let hash_result = unsafe { &mut *(hash_result as *mut [u8; POSEIDON_HASH_BYTES]) };
+            current_syscall_stubs().sol_poseidon(
+                parameters,
+                endianness,
+                &vals[..],
+                hash_result,
+            )
+        }
+
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn sol_big_mod_exp(params: *const u8, result: *mut u8) -> u64 {
+            let params = unsafe { &*(params as *const CBigModExpParams) };
+            if params.base_len > BIG_MOD_EXP_MAX_INPUT_LEN
+                || params.exponent_len > BIG_MOD_EXP_MAX_INPUT_LEN
+                || params.modulus_len > BIG_MOD_EXP_MAX_INPUT_LEN
+            {
+                return SYSCALL_FAILURE;
             }
-            SYSCALL_STUBS.read().unwrap().sol_log_data(&v[..]);
+            let (base, exponent, modulus, result) = unsafe {
+                (
+                    std::slice::from_raw_parts(params.base, params.base_len as _),
+                    std::slice::from_raw_parts(params.exponent, params.exponent_len as _),
+                    std::slice::from_raw_parts(params.modulus, params.modulus_len as _),
+                    std::slice::from_raw_parts_mut(result, params.modulus_len as _),
+                )
+            };
+            current_syscall_stubs()
+                .sol_big_mod_exp(base, exponent, modulus, result)
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_get_processed_sibling_instruction(
             index: u64,
             meta: *mut CProcessedSiblingInstruction,
@@ -255,9 +3980,7 @@ macro_rules! declare_sol_loader_stubs {
             data: *mut u8,
             accounts: *mut CAccountMeta,
         ) -> u64 {
-            let instruction = SYSCALL_STUBS
-                .read()
-                .unwrap()
+            let instruction = current_syscall_stubs()
                 .sol_get_processed_sibling_instruction(index as _);
             match instruction {
                 None => 0, // 0 - No processed sibling instruction.
@@ -291,13 +4014,13 @@ macro_rules! declare_sol_loader_stubs {
                         std::ptr::copy_nonoverlapping(instr.data.as_ptr(), data, data_len);
                         // Now copy the account metas taking into consideration that pubkey is a *const u8.
                         // https://github.com/anza-xyz/pinocchio/blob/main/sdk/pinocchio/src/instruction.rs#L116
+                        let arena = current_instruction_arena();
                         for i in 0..instr.accounts.len() {
                             let account_meta = accounts.add(i);
                             (*account_meta).is_signer = instr.accounts[i].is_signer;
                             (*account_meta).is_writable = instr.accounts[i].is_writable;
-                            (*account_meta).pubkey = Box::leak(Box::new(instr.accounts[i].pubkey))
-                                as *const _
-                                as *const CPubkey;
+                            (*account_meta).pubkey =
+                                arena.alloc_pubkey(instr.accounts[i].pubkey) as *const CPubkey;
                         }
                     }
                     2 // 2 - All good.
@@ -306,6 +4029,7 @@ macro_rules! declare_sol_loader_stubs {
         }
 
         #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
         pub extern "C" fn sol_invoke_signed_c(
             instruction_addr: *const u8,
             account_infos_addr: *const u8,
@@ -314,57 +4038,28 @@ macro_rules! declare_sol_loader_stubs {
             signers_seeds_len: u64,
         ) -> u64 {
             // instruction
-            let cinstr = instruction_addr as *const CInstruction;
-            let instruction = unsafe {
-                Instruction {
-                    program_id: Pubkey::new_from_array(*(*(*cinstr).program_id).as_array()),
-                    accounts: {
-                        (0..(*cinstr).accounts_len)
-                            .map(|i| {
-                                let cam = (*cinstr).accounts.add(i as _);
-                                AccountMeta {
-                                    pubkey: Pubkey::new_from_array(*(*(*cam).pubkey).as_array()),
-                                    is_signer: (*cam).is_signer,
-                                    is_writable: (*cam).is_writable,
-                                }
-                            })
-                            .collect()
-                    },
-                    data: {
-                        let slice =
-                            std::slice::from_raw_parts((*cinstr).data, (*cinstr).data_len as _);
-                        slice.to_vec()
-                    },
-                }
+            let cinstr = unsafe { &*(instruction_addr as *const CInstruction) };
+            let instruction = match unsafe { cinstr.view() } {
+                Ok(view) => view.to_instruction(),
+                Err(error) => return program_error_to_u64(&error.into()),
             };
 
             // account_infos
-            let ai_ptr = account_infos_addr as *const CAccountInfo;
-            let mut account_infos: Vec<AccountInfo<'_>> = vec![];
-            for i in 0..account_infos_len {
-                let cai = unsafe { &*ai_ptr.add(i as _) };
-                let ai = unsafe {
-                    AccountInfo {
-                        key: &*((*cai).key as *const Pubkey),
-                        lamports: std::rc::Rc::new(std::cell::RefCell::new(
-                            &mut *((*cai).lamports as *mut _),
-                        )),
-                        data: {
-                            let slice = std::slice::from_raw_parts_mut(
-                                (*cai).data as _,
-                                (*cai).data_len as _,
-                            );
-                            std::rc::Rc::new(std::cell::RefCell::new(slice))
-                        },
-                        owner: &*((*cai).owner as *const Pubkey),
-                        rent_epoch: (*cai).rent_epoch,
-                        is_signer: (*cai).is_signer,
-                        is_writable: (*cai).is_writable,
-                        executable: (*cai).executable,
-                    }
-                };
-                account_infos.push(ai);
+            let account_infos = match unsafe {
+                CAbiError::c_slice(
+                    account_infos_addr as *const CAccountInfo,
+                    account_infos_len,
+                    "account_infos_addr",
+                )
             }
+            .and_then(|cais| {
+                cais.iter()
+                    .map(|cai| unsafe { cai.to_account_info() })
+                    .collect::<Result<Vec<_>, _>>()
+            }) {
+                Ok(account_infos) => account_infos,
+                Err(error) => return program_error_to_u64(&error.into()),
+            };
 
             // signers_seeds
             let q_fat_ptr = signers_seeds_addr as *const (*const u8, u64);
@@ -383,33 +4078,72 @@ macro_rules! declare_sol_loader_stubs {
             }
 
             let signers_seeds: Vec<_> = qv.iter().map(|e| &e[..]).collect();
-            match SYSCALL_STUBS.read().unwrap().sol_invoke_signed(
+            let stubs = current_syscall_stubs();
+            stubs.sol_clear_return_data();
+            match stubs.sol_invoke_signed(
                 &instruction,
                 &account_infos[..],
                 &signers_seeds[..],
             ) {
                 Ok(_) => {
                     let ai_ptr = account_infos_addr as *mut CAccountInfo;
+                    // Growing past the caller's buffer would write out of bounds.
+                    // Check every account first, so that a failed CPI leaves all
+                    // of them untouched.
+                    let overflows = account_infos.iter().enumerate().any(|(i, acc)| {
+                        acc.data_len() as u64 > unsafe { (*ai_ptr.add(i)).data_capacity }
+                    });
+                    if overflows {
+                        return program_error_to_u64(&ProgramError::InvalidRealloc);
+                    }
                     for (i, acc) in account_infos.iter().enumerate() {
-                        // Update data lens so that caller has them.
+                        // Write the callee's view of the account back so that the caller
+                        // can apply it. The stubs may have swapped the lamports, owner or
+                        // data the `AccountInfo` points to, so copy rather than assume
+                        // the caller's memory was updated in place.
                         let cai = unsafe { &mut *ai_ptr.add(i as _) };
-                        cai.data_len = acc.data_len() as _;
+                        unsafe {
+                            *(cai.lamports as *mut u64) = acc.lamports();
+                            if *(*cai.owner).as_array() != *acc.owner.as_array() {
+                                *(cai.owner as *mut CPubkey) = acc.owner.as_array().into();
+                            }
+                        }
+                        cai.executable = acc.executable;
+                        let data = acc.data.borrow();
+                        if data.as_ptr() != cai.data {
+                            unsafe {
+                                std::ptr::copy_nonoverlapping(
+                                    data.as_ptr(),
+                                    cai.data as *mut u8,
+                                    data.len(),
+                                )
+                            };
+                        }
+                        cai.data_len = data.len() as _;
                     }
                     0
                 }
-                Err(e) => e.into(),
+                Err(e) => program_error_to_u64(&e),
             }
         }
 
-        #[no_mangle]
-        pub extern "C" fn sol_log_pubkey(pubkey: *const u8) {
-            let pubkey = unsafe { &*(pubkey as *const Pubkey) };
-            SYSCALL_STUBS.read().unwrap().sol_log(&pubkey.to_string());
+        /// Lets the program ask whether a syscall aborted it, so that it can
+        /// stop. Not a runtime syscall, so it isn't exported.
+        extern "C" fn program_aborted() -> u64 {
+            is_program_aborted() as u64
         }
 
-        impl SyscallStubsApi {
-            pub fn new() -> Self {
+        /// Building the [`SyscallStubsApi`] table of this loader.
+        pub trait LoaderSyscallStubsApi {
+            /// A table pointing at this loader's exported syscalls, advertising
+            /// every capability.
+            fn new() -> Self;
+        }
+
+        impl LoaderSyscallStubsApi for SyscallStubsApi {
+            fn new() -> Self {
                 Self {
+                    header: SyscallStubsApiHeader::current(SYSCALL_STUBS_CAPS_ALL),
                     sol_get_clock_sysvar: sol_get_clock_sysvar,
                     sol_get_epoch_rewards_sysvar: sol_get_epoch_rewards_sysvar,
                     sol_get_epoch_schedule_sysvar: sol_get_epoch_schedule_sysvar,
@@ -431,8 +4165,73 @@ macro_rules! declare_sol_loader_stubs {
                     sol_get_processed_sibling_instruction: sol_get_processed_sibling_instruction,
                     sol_get_epoch_stake: sol_get_epoch_stake,
                     sol_get_sysvar: sol_get_sysvar,
+                    sol_sha256: sol_sha256,
+                    sol_keccak256: sol_keccak256,
+                    sol_blake3: sol_blake3,
+                    sol_create_program_address: sol_create_program_address,
+                    sol_try_find_program_address: sol_try_find_program_address,
+                    sol_secp256k1_recover: sol_secp256k1_recover,
+                    sol_curve_validate_point: sol_curve_validate_point,
+                    sol_curve_group_op: sol_curve_group_op,
+                    sol_curve_multiscalar_mul: sol_curve_multiscalar_mul,
+                    sol_alt_bn128_group_op: sol_alt_bn128_group_op,
+                    sol_alt_bn128_compression: sol_alt_bn128_compression,
+                    sol_poseidon: sol_poseidon,
+                    sol_big_mod_exp: sol_big_mod_exp,
+                    sol_log_64_: sol_log_64_,
+                    sol_log_pubkey: sol_log_pubkey,
+                    sol_panic_: sol_panic_,
+                    layout_fingerprint: SYSCALL_STUBS_ABI_FINGERPRINT,
+                    program_aborted: program_aborted,
                 }
             }
         }
     };
 }
+
+/// Standard base64 with padding, as the runtime uses for `Program data:` logs.
+#[doc(hidden)]
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(unix)]
+#[doc(hidden)]
+pub use libc;
+
+#[doc(hidden)]
+pub use serde_json;
+
+/// Lowercase hex, as syscall traces store byte strings.
+#[doc(hidden)]
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[doc(hidden)]
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
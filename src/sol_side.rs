@@ -1,6 +1,20 @@
 #![allow(unexpected_cfgs)]
 
 /// A macro providing the necessary stubs for a Solana program.
+///
+/// `declare_sol_app_stubs!(process_instruction)` also exports
+/// `stubs_entrypoint`, through which the loader can stop the program when it
+/// panics or is aborted.
+///
+/// The syscalls `SyscallStubs` has methods for reach the loader however the
+/// program makes them. The others (hashing, program addresses, secp256k1,
+/// curve operations, ...) are methods of `SolAppSyscallStubs` and are only
+/// reachable through `SolAppSyscallStubs::installed()`: natively,
+/// `solana_program`'s `hashv`, `Pubkey::find_program_address` and the like
+/// compute their result themselves and never call them. A program whose
+/// hashing or address derivation must go through the loader's table calls,
+/// say, `SolAppSyscallStubs::installed().unwrap().sol_sha256(vals, &mut hash)`
+/// instead.
 #[macro_export]
 #[cfg(not(target_os = "solana"))]
 macro_rules! declare_sol_app_stubs {
@@ -8,49 +22,284 @@ macro_rules! declare_sol_app_stubs {
         $crate::common_stub_types!();
 
         #[repr(C)]
+        #[derive(Clone, Copy)]
         pub struct SolAppSyscallStubs {
             pub stubs_api: SyscallStubsApi,
         }
 
+        /// The table installed by the last successful `set_stubs` call.
+        static SOL_APP_STUBS_API: std::sync::RwLock<Option<SyscallStubsApi>> =
+            std::sync::RwLock::new(None);
+
+        /// Syscalls not covered by [`SyscallStubs`]. The program reaches them through
+        /// [`SolAppSyscallStubs::installed`] only: the native `solana_program`
+        /// helpers, such as `hashv` and `Pubkey::find_program_address`, don't
+        /// call them.
+        impl SolAppSyscallStubs {
+            /// The stubs installed by the loader, if `set_stubs` has been called.
+            pub fn installed() -> Option<Self> {
+                SOL_APP_STUBS_API
+                    .read()
+                    .unwrap()
+                    .map(|stubs_api| Self { stubs_api })
+            }
+            /// Hands back `result` unless the loader aborted the program during
+            /// the syscall that produced it, in which case the program unwinds
+            /// to `catch_program_abort`.
+            fn checked<T>(&self, result: T) -> T {
+                $crate::sol_side::stop_if_aborted(&self.stubs_api);
+                result
+            }
+            pub fn capabilities(&self) -> u64 {
+                self.stubs_api.header.capabilities
+            }
+            pub fn sol_log_64(&self, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) {
+                self.checked((self.stubs_api.sol_log_64_)(arg1, arg2, arg3, arg4, arg5))
+            }
+            pub fn sol_log_pubkey(&self, pubkey: &Pubkey) {
+                self.checked((self.stubs_api.sol_log_pubkey)(
+                    pubkey as *const _ as *const u8,
+                ))
+            }
+            pub fn sol_panic(&self, file: &str, line: u64, column: u64, message: &str) {
+                (self.stubs_api.sol_panic_)(
+                    file.as_ptr(),
+                    file.len() as u64,
+                    line,
+                    column,
+                    message.as_ptr(),
+                    message.len() as u64,
+                )
+            }
+            pub fn sol_sha256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.checked((self.stubs_api.sol_sha256)(
+                    vals.as_ptr() as *const u8,
+                    vals.len() as u64,
+                    hash_result.as_mut_ptr(),
+                ))
+            }
+            pub fn sol_keccak256(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.checked((self.stubs_api.sol_keccak256)(
+                    vals.as_ptr() as *const u8,
+                    vals.len() as u64,
+                    hash_result.as_mut_ptr(),
+                ))
+            }
+            pub fn sol_blake3(&self, vals: &[&[u8]], hash_result: &mut [u8; HASH_BYTES]) -> u64 {
+                self.checked((self.stubs_api.sol_blake3)(
+                    vals.as_ptr() as *const u8,
+                    vals.len() as u64,
+                    hash_result.as_mut_ptr(),
+                ))
+            }
+            pub fn sol_create_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_create_program_address)(
+                    seeds.as_ptr() as *const u8,
+                    seeds.len() as u64,
+                    program_id as *const _ as *const u8,
+                    address as *mut _ as *mut u8,
+                ))
+            }
+            pub fn sol_try_find_program_address(
+                &self,
+                seeds: &[&[u8]],
+                program_id: &Pubkey,
+                address: &mut Pubkey,
+                bump_seed: &mut u8,
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_try_find_program_address)(
+                    seeds.as_ptr() as *const u8,
+                    seeds.len() as u64,
+                    program_id as *const _ as *const u8,
+                    address as *mut _ as *mut u8,
+                    bump_seed,
+                ))
+            }
+            /// Returns 0 or one of the `SECP256K1_RECOVER_ERROR_*` codes.
+            pub fn sol_secp256k1_recover(
+                &self,
+                hash: &[u8; HASH_BYTES],
+                recovery_id: u64,
+                signature: &[u8; SECP256K1_SIGNATURE_LENGTH],
+                result: &mut [u8; SECP256K1_PUBLIC_KEY_LENGTH],
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_secp256k1_recover)(
+                    hash.as_ptr(),
+                    recovery_id,
+                    signature.as_ptr(),
+                    result.as_mut_ptr(),
+                ))
+            }
+            /// Returns 0 if `point` is a valid point on the curve, 1 otherwise.
+            pub fn sol_curve_validate_point(
+                &self,
+                curve_id: u64,
+                point: &[u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_curve_validate_point)(
+                    curve_id,
+                    point.as_ptr(),
+                    &mut u8::default(),
+                ))
+            }
+            /// For `CURVE25519_MUL`, `left_input` is the scalar and `right_input` the point.
+            pub fn sol_curve_group_op(
+                &self,
+                curve_id: u64,
+                group_op: u64,
+                left_input: &[u8; CURVE25519_POINT_BYTES],
+                right_input: &[u8; CURVE25519_POINT_BYTES],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_curve_group_op)(
+                    curve_id,
+                    group_op,
+                    left_input.as_ptr(),
+                    right_input.as_ptr(),
+                    result_point.as_mut_ptr(),
+                ))
+            }
+            pub fn sol_curve_multiscalar_mul(
+                &self,
+                curve_id: u64,
+                scalars: &[[u8; CURVE25519_SCALAR_BYTES]],
+                points: &[[u8; CURVE25519_POINT_BYTES]],
+                result_point: &mut [u8; CURVE25519_POINT_BYTES],
+            ) -> u64 {
+                if scalars.len() != points.len() {
+                    return SYSCALL_FAILURE;
+                }
+                self.checked((self.stubs_api.sol_curve_multiscalar_mul)(
+                    curve_id,
+                    scalars.as_ptr() as *const u8,
+                    points.as_ptr() as *const u8,
+                    points.len() as u64,
+                    result_point.as_mut_ptr(),
+                ))
+            }
+            /// `result` must hold at least `alt_bn128_group_op_result_len(group_op)` bytes.
+            pub fn sol_alt_bn128_group_op(
+                &self,
+                group_op: u64,
+                input: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                match alt_bn128_group_op_result_len(group_op) {
+                    Some(len) if result.len() >= len => {
+                        self.checked((self.stubs_api.sol_alt_bn128_group_op)(
+                            group_op,
+                            input.as_ptr(),
+                            input.len() as u64,
+                            result.as_mut_ptr(),
+                        ))
+                    }
+                    _ => SYSCALL_FAILURE,
+                }
+            }
+            /// `result` must hold at least `alt_bn128_compression_result_len(op)` bytes.
+            pub fn sol_alt_bn128_compression(
+                &self,
+                op: u64,
+                input: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                match alt_bn128_compression_result_len(op) {
+                    Some(len) if result.len() >= len => {
+                        self.checked((self.stubs_api.sol_alt_bn128_compression)(
+                            op,
+                            input.as_ptr(),
+                            input.len() as u64,
+                            result.as_mut_ptr(),
+                        ))
+                    }
+                    _ => SYSCALL_FAILURE,
+                }
+            }
+            pub fn sol_poseidon(
+                &self,
+                parameters: u64,
+                endianness: u64,
+                vals: &[&[u8]],
+                hash_result: &mut [u8; POSEIDON_HASH_BYTES],
+            ) -> u64 {
+                self.checked((self.stubs_api.sol_poseidon)(
+                    parameters,
+                    endianness,
+                    vals.as_ptr() as *const u8,
+                    vals.len() as u64,
+                    hash_result.as_mut_ptr(),
+                ))
+            }
+            /// `result` must be as long as `modulus`.
+            pub fn sol_big_mod_exp(
+                &self,
+                base: &[u8],
+                exponent: &[u8],
+                modulus: &[u8],
+                result: &mut [u8],
+            ) -> u64 {
+                if result.len() != modulus.len() {
+                    return SYSCALL_FAILURE;
+                }
+                let params = CBigModExpParams {
+                    base: base.as_ptr(),
+                    base_len: base.len() as u64,
+                    exponent: exponent.as_ptr(),
+                    exponent_len: exponent.len() as u64,
+                    modulus: modulus.as_ptr(),
+                    modulus_len: modulus.len() as u64,
+                };
+                self.checked((self.stubs_api.sol_big_mod_exp)(
+                    &params as *const _ as *const u8,
+                    result.as_mut_ptr(),
+                ))
+            }
+        }
+
         impl SyscallStubs for SolAppSyscallStubs {
             fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_clock_sysvar)(var_addr)
+                self.checked((self.stubs_api.sol_get_clock_sysvar)(var_addr))
             }
             fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_epoch_rewards_sysvar)(var_addr)
+                self.checked((self.stubs_api.sol_get_epoch_rewards_sysvar)(var_addr))
             }
             fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_epoch_schedule_sysvar)(var_addr)
+                self.checked((self.stubs_api.sol_get_epoch_schedule_sysvar)(var_addr))
             }
             fn sol_get_epoch_stake(&self, vote_address: *const u8) -> u64 {
-                (self.stubs_api.sol_get_epoch_stake)(vote_address)
+                self.checked((self.stubs_api.sol_get_epoch_stake)(vote_address))
             }
             fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_fees_sysvar)(var_addr)
+                self.checked((self.stubs_api.sol_get_fees_sysvar)(var_addr))
             }
             fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_last_restart_slot)(var_addr)
+                self.checked((self.stubs_api.sol_get_last_restart_slot)(var_addr))
             }
             fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
-                (self.stubs_api.sol_get_rent_sysvar)(var_addr)
+                self.checked((self.stubs_api.sol_get_rent_sysvar)(var_addr))
             }
             fn sol_get_stack_height(&self) -> u64 {
-                (self.stubs_api.sol_get_stack_height)()
+                self.checked((self.stubs_api.sol_get_stack_height)())
             }
             fn sol_remaining_compute_units(&self) -> u64 {
-                (self.stubs_api.sol_remaining_compute_units)()
+                self.checked((self.stubs_api.sol_remaining_compute_units)())
             }
             unsafe fn sol_memcmp(&self, s1: *const u8, s2: *const u8, n: usize, result: *mut i32) {
-                (self.stubs_api.sol_memcmp_)(s1, s2, n as u64, result);
+                self.checked((self.stubs_api.sol_memcmp_)(s1, s2, n as u64, result));
             }
             unsafe fn sol_memcpy(&self, dst: *mut u8, src: *const u8, n: usize) {
-                (self.stubs_api.sol_memcpy_)(dst, src, n as u64)
+                self.checked((self.stubs_api.sol_memcpy_)(dst, src, n as u64))
             }
             unsafe fn sol_memmove(&self, dst: *mut u8, src: *const u8, n: usize) {
-                (self.stubs_api.sol_memmove_)(dst, src, n as u64)
+                self.checked((self.stubs_api.sol_memmove_)(dst, src, n as u64))
             }
             unsafe fn sol_memset(&self, s: *mut u8, c: u8, n: usize) {
-                (self.stubs_api.sol_memset_)(s, c, n as u64)
+                self.checked((self.stubs_api.sol_memset_)(s, c, n as u64))
             }
             fn sol_get_sysvar(
                 &self,
@@ -59,38 +308,53 @@ macro_rules! declare_sol_app_stubs {
                 offset: u64,
                 length: u64,
             ) -> u64 {
-                (self.stubs_api.sol_get_sysvar)(sysvar_id_addr, var_addr, offset, length)
+                self.checked((self.stubs_api.sol_get_sysvar)(
+                    sysvar_id_addr,
+                    var_addr,
+                    offset,
+                    length,
+                ))
             }
             fn sol_log_compute_units(&self) {
-                (self.stubs_api.sol_log_compute_units_)()
+                self.checked((self.stubs_api.sol_log_compute_units_)())
             }
             fn sol_log(&self, message: &str) {
-                (self.stubs_api.sol_log_)(message.as_ptr(), message.len() as u64)
+                self.checked((self.stubs_api.sol_log_)(
+                    message.as_ptr(),
+                    message.len() as u64,
+                ))
             }
             fn sol_log_data(&self, fields: &[&[u8]]) {
-                (self.stubs_api.sol_log_data)(fields.as_ptr() as *const u8, fields.len() as u64);
+                self.checked((self.stubs_api.sol_log_data)(
+                    fields.as_ptr() as *const u8,
+                    fields.len() as u64,
+                ));
             }
             fn sol_set_return_data(&self, data: &[u8]) {
-                (self.stubs_api.sol_set_return_data)(data.as_ptr(), data.len() as u64);
+                // The loader aborts the program if `data` is too large.
+                self.checked((self.stubs_api.sol_set_return_data)(
+                    data.as_ptr(),
+                    data.len() as u64,
+                ));
             }
             fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+                // The syscall copies at most the buffer's length and returns the
+                // full length, so one call with the largest buffer is enough.
                 let mut program_id = CPubkey::from([0u8; 32]);
-                let data_bytes_to_alloc =
-                    (self.stubs_api.sol_get_return_data)(&mut u8::default(), 0, &mut program_id);
-                if data_bytes_to_alloc == 0 {
-                    return None;
-                }
-                let mut vdata = vec![0u8; data_bytes_to_alloc as usize];
-                let same_bytes_num_expected = (self.stubs_api.sol_get_return_data)(
-                    vdata.as_mut_ptr(),
-                    vdata.len() as _,
+                let mut buf = [0u8; MAX_RETURN_DATA];
+                let size = self.checked((self.stubs_api.sol_get_return_data)(
+                    buf.as_mut_ptr(),
+                    buf.len() as _,
                     &mut program_id,
-                );
-                if same_bytes_num_expected == data_bytes_to_alloc {
-                    Some((Pubkey::new_from_array(*program_id.as_array()), vdata))
-                } else {
-                    None
+                ));
+                if size == 0 {
+                    return None;
                 }
+                let size = std::cmp::min(size as usize, MAX_RETURN_DATA);
+                Some((
+                    Pubkey::new_from_array(*program_id.as_array()),
+                    buf[..size].to_vec(),
+                ))
             }
             fn sol_get_processed_sibling_instruction(&self, index: usize) -> Option<Instruction> {
                 let mut meta = CProcessedSiblingInstruction {
@@ -98,24 +362,24 @@ macro_rules! declare_sol_app_stubs {
                     data_len: 0,
                 };
                 let mut program_id = CPubkey::from([0u8; 32]);
-                if 1 == (self.stubs_api.sol_get_processed_sibling_instruction)(
+                if 1 == self.checked((self.stubs_api.sol_get_processed_sibling_instruction)(
                     index as _,
                     &mut meta,
                     &mut program_id,
                     &mut u8::default(),
                     &mut CAccountMeta::default(),
-                ) {
+                )) {
                     let accounts_to_alloc = meta.accounts_len;
                     let data_bytes_to_alloc = meta.data_len;
                     let mut caccount_metas = vec![CAccountMeta::default(); accounts_to_alloc as _];
                     let mut vdata = vec![0u8; data_bytes_to_alloc as _];
-                    let res = (self.stubs_api.sol_get_processed_sibling_instruction)(
+                    let res = self.checked((self.stubs_api.sol_get_processed_sibling_instruction)(
                         index as _,
                         &mut meta,
                         &mut program_id,
                         vdata.as_mut_ptr(),
                         caccount_metas.as_mut_ptr(),
-                    );
+                    ));
                     if res != 0 && res != 1 {
                         let mut account_metas = vec![];
                         for cai in &caccount_metas {
@@ -143,72 +407,781 @@ macro_rules! declare_sol_app_stubs {
                 account_infos: &[AccountInfo],
                 signers_seeds: &[&[&[u8]]],
             ) -> ProgramResult {
-                let mut caccounts = vec![];
-                for account_meta in &instruction.accounts {
-                    let caccount = CAccountMeta {
-                        is_signer: account_meta.is_signer,
-                        is_writable: account_meta.is_writable,
-                        pubkey: &account_meta.pubkey as *const _ as *const CPubkey,
-                    };
-                    caccounts.push(caccount);
-                }
-                let cinstr = CInstruction {
-                    program_id: &instruction.program_id as *const _ as *const CPubkey,
-                    accounts_len: instruction.accounts.len() as _,
-                    data_len: instruction.data.len() as _,
-                    accounts: caccounts.as_ptr(),
-                    data: instruction.data.as_ptr(),
-                };
-                let mut caccount_infos = vec![];
-                for account_info in account_infos {
-                    let lamports_ref = &mut *account_info.lamports.borrow_mut();
-                    let data_ref = &mut *account_info.data.borrow_mut();
-                    let caccount_info = CAccountInfo {
-                        is_signer: account_info.is_signer,
-                        is_writable: account_info.is_writable,
-                        executable: account_info.executable,
-                        rent_epoch: account_info.rent_epoch,
-                        data_len: data_ref.len() as _,
-                        data: data_ref.as_mut_ptr(),
-                        lamports: *lamports_ref as *mut u64,
-                        key: account_info.key as *const _ as *const CPubkey,
-                        owner: account_info.owner as *const _ as *const CPubkey,
-                    };
-                    let cai = &caccount_info;
-                    let key: &Pubkey = unsafe { &*((*cai).key as *const Pubkey) };
-                    let owner: &Pubkey = unsafe { &*((*cai).owner as *const Pubkey) };
-                    caccount_infos.push(caccount_info);
-                }
+                let cinstr = CInstructionBuf::try_from(instruction)?;
+                // Only accounts of the input `stubs_entrypoint` deserialized are
+                // laid out the way `AccountInfo::realloc` assumes.
+                let serialized = |ai: &AccountInfo| $crate::sol_side::is_serialized_input(ai.key);
+                let mut caccount_infos =
+                    unsafe { CAccountInfoBuf::from_accounts(account_infos, serialized) };
 
-                let res = (self.stubs_api.sol_invoke_signed_c)(
-                    &cinstr as *const _ as *const u8,
-                    caccount_infos.as_ptr() as *const u8,
+                let res = self.checked((self.stubs_api.sol_invoke_signed_c)(
+                    cinstr.as_ptr(),
+                    caccount_infos.as_mut_ptr(),
                     caccount_infos.len() as _,
                     signers_seeds.as_ptr() as *const u8,
                     signers_seeds.len() as _,
-                );
+                ));
                 if res == 0 {
-                    for (i, ai) in account_infos.iter().enumerate() {
-                        let new_data_slice = unsafe {
-                            std::slice::from_raw_parts_mut(
-                                (*ai.data.borrow_mut()).as_mut_ptr(),
-                                caccount_infos[i].data_len as _,
-                            )
-                        };
-                        *ai.data.borrow_mut() = new_data_slice;
-                        assert!(ai.lamports() == unsafe { *caccount_infos[i].lamports });
+                    // The loader wrote the callee's view of every account back into
+                    // `caccount_infos`; apply it the way the runtime updates caller
+                    // accounts after a CPI. Check every account first, so that a
+                    // failed CPI leaves all of them untouched.
+                    for (ai, cai) in account_infos.iter().zip(caccount_infos.as_slice()) {
+                        if cai.data_len > cai.data_capacity {
+                            return Err(ProgramError::InvalidRealloc);
+                        }
+                        if ai.try_borrow_mut_data().is_err()
+                            || ai.try_borrow_mut_lamports().is_err()
+                        {
+                            return Err(ProgramError::AccountBorrowFailed);
+                        }
+                    }
+                    for (ai, cai) in account_infos.iter().zip(caccount_infos.as_slice()) {
+                        **ai.lamports.borrow_mut() = unsafe { *cai.lamports };
+                        let owner = unsafe { &*(cai.owner as *const Pubkey) };
+                        if ai.owner != owner {
+                            ai.assign(owner);
+                        }
+                        let new_len = cai.data_len as usize;
+                        if new_len < ai.data_len() {
+                            // The runtime zeroes the bytes an account shrinks by.
+                            ai.data.borrow_mut()[new_len..].fill(0);
+                        }
+                        // Only resized accounts are touched. The loader already
+                        // wrote any grown bytes.
+                        if new_len != ai.data_len() {
+                            if serialized(ai) {
+                                // `realloc` keeps the serialized length in sync,
+                                // which the runtime put before the data.
+                                #[allow(deprecated)]
+                                ai.realloc(new_len, false)?;
+                            } else {
+                                // Other accounts can only have shrunk.
+                                let mut data = ai.data.borrow_mut();
+                                let shrunk = &mut std::mem::take(&mut *data)[..new_len];
+                                *data = shrunk;
+                            }
+                        }
+                        // `executable` can't be written through `&AccountInfo`; like the
+                        // runtime, the caller keeps its pre-CPI view of it.
                     }
                     Ok(())
                 } else {
-                    Err(ProgramError::Custom(res as _))
+                    Err(program_error_from_u64(res))
                 }
             }
         }
 
+        /// Installs the loader's syscall table.
+        ///
+        /// The header is checked first so that a loader built against an
+        /// incompatible release of this crate is rejected instead of having its
+        /// table misread. Returns [`SET_STUBS_SUCCESS`] or a [`SetStubsError`] code.
         #[no_mangle]
-        pub extern "C" fn set_stubs(stubs_api: SyscallStubsApi) {
+        // Called by the loader with its own table, whose pointer and header
+        // `accept` checks before reading the rest.
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn set_stubs(stubs_api: *const SyscallStubsApi) -> u64 {
+            let stubs_api = match unsafe { SyscallStubsApi::accept(stubs_api) } {
+                Ok(stubs_api) => stubs_api,
+                // The program mustn't write to the loader's stderr; the code
+                // tells the loader why.
+                Err(e) => return e.code(),
+            };
+            *SOL_APP_STUBS_API.write().unwrap() = Some(stubs_api);
+            if stubs_api.header.capabilities & SYSCALL_STUBS_CAP_PANIC != 0 {
+                // Installed only once: it reads whichever table is installed when
+                // the program panics.
+                static INSTALL_PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+                INSTALL_PANIC_HOOK.call_once(|| std::panic::set_hook(Box::new(sol_app_panic_hook)));
+            }
             let stubs = Box::new(SolAppSyscallStubs { stubs_api });
             let _ = set_syscall_stubs(stubs);
+            SET_STUBS_SUCCESS
+        }
+
+        /// The program's [`SYSCALL_STUBS_ABI_FINGERPRINT`], for loaders to
+        /// compare with their own before calling `set_stubs`.
+        #[no_mangle]
+        pub extern "C" fn stubs_abi_fingerprint() -> u64 {
+            SYSCALL_STUBS_ABI_FINGERPRINT
+        }
+
+        /// Reports program panics to the loader, which fails the instruction.
+        /// The panic then unwinds to `catch_program_abort`.
+        fn sol_app_panic_hook(info: &std::panic::PanicHookInfo<'_>) {
+            let (file, line, column, message) = $crate::sol_side::panic_report(info);
+            if let Some(stubs) = SolAppSyscallStubs::installed() {
+                stubs.sol_panic(file, line, column, message);
+            }
+        }
+    };
+    ($process_instruction:path) => {
+        $crate::declare_sol_app_stubs!();
+
+        /// The entrypoint loaders of this crate call instead of `entrypoint`:
+        /// it runs the instruction through `catch_program_abort`, so that the
+        /// loader can stop the program. Needs `deserialize` from
+        /// `solana_program::entrypoint` in scope.
+        #[no_mangle]
+        pub unsafe extern "C" fn stubs_entrypoint(input: *mut u8) -> u64 {
+            $crate::sol_side::catch_program_abort(|| {
+                let (program_id, accounts, instruction_data) = unsafe { deserialize(input) };
+                // The program id is the last thing the runtime serializes.
+                let end = (program_id as *const Pubkey).wrapping_add(1) as *const u8;
+                $crate::sol_side::with_serialized_input(input..end, || {
+                    program_result_to_u64(&$process_instruction(
+                        program_id,
+                        &accounts,
+                        instruction_data,
+                    ))
+                })
+            })
+        }
+    };
+}
+
+/// A macro providing the raw `sol_*` syscalls for a program that calls them
+/// directly, as pinocchio programs do, instead of going through
+/// `solana_program::program_stubs`.
+///
+/// It exports `set_stubs` and every syscall the loader's [`SyscallStubsApi`]
+/// carries, each forwarding to the table installed by `set_stubs`. It needs no
+/// `solana_program` imports, and must not be used alongside
+/// [`declare_sol_app_stubs!`] in the same program.
+///
+/// Upstream pinocchio compiles its syscalls out of native builds, so the
+/// exports are only reached by code that declares the raw syscalls itself, as
+/// `solana_define_syscall::definitions` does on every target.
+///
+/// `declare_pinocchio_app_stubs!(process_entrypoint)` also exports
+/// `stubs_entrypoint`, which runs `process_entrypoint`, an
+/// `unsafe fn(*mut u8) -> u64` doing what the program's `entrypoint` does,
+/// under [`catch_program_abort`]. A panic then stops the program, and so does
+/// an abort the loader reports during a syscall, provided the program declares
+/// the syscalls `extern "C-unwind"`, the ABI they are exported with.
+///
+/// [`SyscallStubsApi`]: crate::common::abi::SyscallStubsApi
+/// [`catch_program_abort`]: crate::sol_side::catch_program_abort
+#[macro_export]
+#[cfg(not(target_os = "solana"))]
+macro_rules! declare_pinocchio_app_stubs {
+    () => {
+        pub use $crate::common::abi::*;
+
+        /// The table installed by the last successful `set_stubs` call.
+        static PINOCCHIO_APP_STUBS_API: std::sync::RwLock<Option<SyscallStubsApi>> =
+            std::sync::RwLock::new(None);
+
+        /// The installed table. Until `set_stubs` is called, syscalls that
+        /// return a code fail with `SYSCALL_FAILURE` and the others do nothing.
+        fn installed_stubs_api() -> Option<SyscallStubsApi> {
+            *PINOCCHIO_APP_STUBS_API.read().unwrap()
+        }
+
+        /// Makes `syscall` through the installed table, or returns `failure`
+        /// without one. If the loader aborted the program during the syscall,
+        /// the program unwinds to `catch_program_abort` instead.
+        fn forward<T>(failure: T, syscall: impl FnOnce(SyscallStubsApi) -> T) -> T {
+            match installed_stubs_api() {
+                Some(stubs_api) => {
+                    let result = syscall(stubs_api);
+                    $crate::sol_side::stop_if_aborted(&stubs_api);
+                    result
+                }
+                None => failure,
+            }
+        }
+
+        /// Installs the loader's syscall table. Returns [`SET_STUBS_SUCCESS`] or
+        /// a [`SetStubsError`] code.
+        #[no_mangle]
+        // Called by the loader with its own table, whose pointer and header
+        // `accept` checks before reading the rest.
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn set_stubs(stubs_api: *const SyscallStubsApi) -> u64 {
+            let stubs_api = match unsafe { SyscallStubsApi::accept(stubs_api) } {
+                Ok(stubs_api) => stubs_api,
+                Err(e) => return e.code(),
+            };
+            *PINOCCHIO_APP_STUBS_API.write().unwrap() = Some(stubs_api);
+            if stubs_api.header.capabilities & SYSCALL_STUBS_CAP_PANIC != 0 {
+                // Installed only once, for the same reason as in `declare_sol_app_stubs!`.
+                static INSTALL_PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+                INSTALL_PANIC_HOOK
+                    .call_once(|| std::panic::set_hook(Box::new(pinocchio_app_panic_hook)));
+            }
+            SET_STUBS_SUCCESS
+        }
+
+        /// The program's [`SYSCALL_STUBS_ABI_FINGERPRINT`], for loaders to
+        /// compare with their own before calling `set_stubs`.
+        #[no_mangle]
+        pub extern "C" fn stubs_abi_fingerprint() -> u64 {
+            SYSCALL_STUBS_ABI_FINGERPRINT
+        }
+
+        /// Reports program panics to the loader, which fails the instruction.
+        /// The panic then unwinds to `catch_program_abort`.
+        fn pinocchio_app_panic_hook(info: &std::panic::PanicHookInfo<'_>) {
+            let (file, line, column, message) = $crate::sol_side::panic_report(info);
+            if let Some(stubs_api) = *PINOCCHIO_APP_STUBS_API.read().unwrap() {
+                (stubs_api.sol_panic_)(
+                    file.as_ptr(),
+                    file.len() as u64,
+                    line,
+                    column,
+                    message.as_ptr(),
+                    message.len() as u64,
+                );
+            }
+        }
+
+        /// The runtime's `sol_panic_`, which carries no message.
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_panic_(
+            file: *const u8,
+            len: u64,
+            line: u64,
+            column: u64,
+        ) -> ! {
+            if let Some(stubs_api) = installed_stubs_api() {
+                (stubs_api.sol_panic_)(file, len, line, column, "".as_ptr(), 0);
+            }
+            $crate::sol_side::stop_panicked_program()
+        }
+
+        /// Where pinocchio keeps how many bytes an account has been resized by
+        /// during the instruction: the 4 bytes before its key, which the runtime
+        /// serializes as zeros.
+        fn pinocchio_resize_delta(account_info: &SolAccountInfo) -> *mut i32 {
+            (account_info.key as *mut u8).wrapping_sub(4) as *mut i32
+        }
+
+        /// Takes the runtime's [`SolAccountInfo`]s and hands the loader the
+        /// [`CAccountInfo`]s it expects, then applies the resizes it reports.
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_invoke_signed_c(
+            instruction_addr: *const u8,
+            account_infos_addr: *const u8,
+            account_infos_len: u64,
+            signers_seeds_addr: *const u8,
+            signers_seeds_len: u64,
+        ) -> u64 {
+            // The resizes are written back through this pointer, so no shared
+            // slice of the account infos is formed.
+            let account_infos = account_infos_addr as *mut SolAccountInfo;
+            let mut caccount_infos: Vec<CAccountInfo> = (0..account_infos_len as usize)
+                .map(|i| {
+                    let account_info = unsafe { &*account_infos.add(i) };
+                    // The runtime serializes `MAX_PERMITTED_DATA_INCREASE` spare
+                    // bytes after the data the account started the instruction with.
+                    let resize_delta = unsafe { *pinocchio_resize_delta(account_info) };
+                    let original_data_len =
+                        (account_info.data_len as i64 - resize_delta as i64) as u64;
+                    CAccountInfo {
+                        key: account_info.key,
+                        lamports: account_info.lamports,
+                        data_len: account_info.data_len,
+                        data: account_info.data,
+                        owner: account_info.owner,
+                        rent_epoch: account_info.rent_epoch,
+                        is_signer: account_info.is_signer,
+                        is_writable: account_info.is_writable,
+                        executable: account_info.executable,
+                        original_data_len,
+                        data_capacity: (original_data_len + MAX_PERMITTED_DATA_INCREASE as u64)
+                            .min(MAX_PERMITTED_DATA_LENGTH),
+                    }
+                })
+                .collect();
+            let res = forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_invoke_signed_c)(
+                    instruction_addr,
+                    caccount_infos.as_mut_ptr() as *const u8,
+                    account_infos_len,
+                    signers_seeds_addr,
+                    signers_seeds_len,
+                )
+            });
+            if res == 0 {
+                // The loader wrote lamports, owners and data through the shared
+                // pointers; only the lengths live in `caccount_infos`.
+                for (i, cai) in caccount_infos.iter().enumerate() {
+                    let ai = unsafe { &mut *account_infos.add(i) };
+                    if cai.data_len == ai.data_len {
+                        continue;
+                    }
+                    let data = ai.data as *mut u8;
+                    unsafe {
+                        if cai.data_len < ai.data_len {
+                            // The runtime zeroes the bytes an account shrinks by.
+                            std::ptr::write_bytes(
+                                data.add(cai.data_len as usize),
+                                0,
+                                (ai.data_len - cai.data_len) as usize,
+                            );
+                        }
+                        // The serialized length precedes the data, and the runtime
+                        // also updates the caller's account info.
+                        *(data.offset(-8) as *mut u64) = cai.data_len;
+                        *pinocchio_resize_delta(ai) +=
+                            (cai.data_len as i64 - ai.data_len as i64) as i32;
+                    }
+                    ai.data_len = cai.data_len;
+                }
+            }
+            res
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_log_(message: *const u8, len: u64) {
+            forward((), |stubs_api| (stubs_api.sol_log_)(message, len))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_log_compute_units_() {
+            forward((), |stubs_api| (stubs_api.sol_log_compute_units_)())
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_remaining_compute_units() -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_remaining_compute_units)()
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_clock_sysvar(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_clock_sysvar)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_epoch_schedule_sysvar(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_epoch_schedule_sysvar)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_fees_sysvar(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_fees_sysvar)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_rent_sysvar(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_rent_sysvar)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_last_restart_slot(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_last_restart_slot)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_sysvar(
+            sysvar_id_addr: *const u8,
+            result: *mut u8,
+            offset: u64,
+            length: u64,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_sysvar)(sysvar_id_addr, result, offset, length)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_memcpy_(dst: *mut u8, src: *const u8, n: u64) {
+            forward((), |stubs_api| (stubs_api.sol_memcpy_)(dst, src, n))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_memmove_(dst: *mut u8, src: *const u8, n: u64) {
+            forward((), |stubs_api| (stubs_api.sol_memmove_)(dst, src, n))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_memcmp_(
+            s1: *const u8,
+            s2: *const u8,
+            n: u64,
+            result: *mut i32,
+        ) {
+            forward((), |stubs_api| (stubs_api.sol_memcmp_)(s1, s2, n, result))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_memset_(s: *mut u8, c: u8, n: u64) {
+            forward((), |stubs_api| (stubs_api.sol_memset_)(s, c, n))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_return_data(
+            data: *mut u8,
+            length: u64,
+            program_id: *mut CPubkey,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_return_data)(data, length, program_id)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_set_return_data(data: *const u8, length: u64) {
+            forward((), |stubs_api| {
+                (stubs_api.sol_set_return_data)(data, length)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_log_data(data: *const u8, data_len: u64) {
+            forward((), |stubs_api| (stubs_api.sol_log_data)(data, data_len))
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_processed_sibling_instruction(
+            index: u64,
+            meta: *mut CProcessedSiblingInstruction,
+            program_id: *mut CPubkey,
+            data: *mut u8,
+            accounts: *mut CAccountMeta,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_processed_sibling_instruction)(
+                    index, meta, program_id, data, accounts,
+                )
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_stack_height() -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_stack_height)()
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_epoch_rewards_sysvar(addr: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_epoch_rewards_sysvar)(addr)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_get_epoch_stake(vote_address: *const u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_get_epoch_stake)(vote_address)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_sha256(
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_sha256)(vals, val_len, hash_result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_keccak256(
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_keccak256)(vals, val_len, hash_result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_blake3(
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_blake3)(vals, val_len, hash_result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_create_program_address(
+            seeds_addr: *const u8,
+            seeds_len: u64,
+            program_id_addr: *const u8,
+            address_bytes_addr: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_create_program_address)(
+                    seeds_addr,
+                    seeds_len,
+                    program_id_addr,
+                    address_bytes_addr,
+                )
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_try_find_program_address(
+            seeds_addr: *const u8,
+            seeds_len: u64,
+            program_id_addr: *const u8,
+            address_bytes_addr: *mut u8,
+            bump_seed_addr: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_try_find_program_address)(
+                    seeds_addr,
+                    seeds_len,
+                    program_id_addr,
+                    address_bytes_addr,
+                    bump_seed_addr,
+                )
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_secp256k1_recover(
+            hash: *const u8,
+            recovery_id: u64,
+            signature: *const u8,
+            result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_secp256k1_recover)(hash, recovery_id, signature, result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_curve_validate_point(
+            curve_id: u64,
+            point_addr: *const u8,
+            result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_curve_validate_point)(curve_id, point_addr, result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_curve_group_op(
+            curve_id: u64,
+            group_op: u64,
+            left_input_addr: *const u8,
+            right_input_addr: *const u8,
+            result_point_addr: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_curve_group_op)(
+                    curve_id,
+                    group_op,
+                    left_input_addr,
+                    right_input_addr,
+                    result_point_addr,
+                )
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_curve_multiscalar_mul(
+            curve_id: u64,
+            scalars_addr: *const u8,
+            points_addr: *const u8,
+            points_len: u64,
+            result_point_addr: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_curve_multiscalar_mul)(
+                    curve_id,
+                    scalars_addr,
+                    points_addr,
+                    points_len,
+                    result_point_addr,
+                )
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_alt_bn128_group_op(
+            group_op: u64,
+            input: *const u8,
+            input_size: u64,
+            result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_alt_bn128_group_op)(group_op, input, input_size, result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_alt_bn128_compression(
+            op: u64,
+            input: *const u8,
+            input_size: u64,
+            result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_alt_bn128_compression)(op, input, input_size, result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_poseidon(
+            parameters: u64,
+            endianness: u64,
+            vals: *const u8,
+            val_len: u64,
+            hash_result: *mut u8,
+        ) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_poseidon)(parameters, endianness, vals, val_len, hash_result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_big_mod_exp(params: *const u8, result: *mut u8) -> u64 {
+            forward(SYSCALL_FAILURE, |stubs_api| {
+                (stubs_api.sol_big_mod_exp)(params, result)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_log_64_(
+            arg1: u64,
+            arg2: u64,
+            arg3: u64,
+            arg4: u64,
+            arg5: u64,
+        ) {
+            forward((), |stubs_api| {
+                (stubs_api.sol_log_64_)(arg1, arg2, arg3, arg4, arg5)
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C-unwind" fn sol_log_pubkey(pubkey_addr: *const u8) {
+            forward((), |stubs_api| (stubs_api.sol_log_pubkey)(pubkey_addr))
+        }
+    };
+    ($process_entrypoint:path) => {
+        $crate::declare_pinocchio_app_stubs!();
+
+        /// The entrypoint loaders of this crate call instead of `entrypoint`:
+        /// it runs the instruction through `catch_program_abort`, so that the
+        /// loader can stop the program.
+        #[no_mangle]
+        pub unsafe extern "C" fn stubs_entrypoint(input: *mut u8) -> u64 {
+            $crate::sol_side::catch_program_abort(|| unsafe { $process_entrypoint(input) })
         }
     };
 }
+
+/// Splits a panic into the location and message reported through `sol_panic_`.
+#[doc(hidden)]
+pub fn panic_report<'a>(info: &'a std::panic::PanicHookInfo<'_>) -> (&'a str, u64, u64, &'a str) {
+    let (file, line, column) = info
+        .location()
+        .map(|l| (l.file(), l.line() as u64, l.column() as u64))
+        .unwrap_or(("<unknown>", 0, 0));
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>");
+    (file, line, column, message)
+}
+
+std::thread_local! {
+    /// How many [`catch_program_abort`] calls are running on this thread.
+    static PROGRAM_ABORT_CATCHERS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Unwind payload that stops a program the loader aborted.
+struct ProgramAborted;
+
+/// Runs a program's entrypoint so that the program can be stopped: when it
+/// panics, or when the loader aborts it during a syscall, it unwinds back
+/// here and [`ENTRYPOINT_ABORTED`] is returned. The loader knows why.
+///
+/// Native entrypoints are `extern "C"`, which an unwind can't cross without
+/// ending the process, so a program can only be stopped inside this call.
+///
+/// [`ENTRYPOINT_ABORTED`]: crate::common::abi::ENTRYPOINT_ABORTED
+pub fn catch_program_abort(entrypoint: impl FnOnce() -> u64) -> u64 {
+    PROGRAM_ABORT_CATCHERS.with(|catchers| catchers.set(catchers.get() + 1));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(entrypoint));
+    PROGRAM_ABORT_CATCHERS.with(|catchers| catchers.set(catchers.get() - 1));
+    result.unwrap_or(crate::common::abi::ENTRYPOINT_ABORTED)
+}
+
+std::thread_local! {
+    /// The inputs `stubs_entrypoint` is running programs over on this thread.
+    static SERIALIZED_INPUTS: std::cell::RefCell<Vec<std::ops::Range<usize>>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` with `input` recorded as an input the runtime serialized, whose
+/// accounts carry their original data length and spare bytes for growing.
+#[doc(hidden)]
+pub fn with_serialized_input<T>(input: std::ops::Range<*const u8>, f: impl FnOnce() -> T) -> T {
+    /// Forgets the input, also when the program is stopped.
+    struct Forget;
+    impl Drop for Forget {
+        fn drop(&mut self) {
+            SERIALIZED_INPUTS.with(|inputs| inputs.borrow_mut().pop());
+        }
+    }
+    SERIALIZED_INPUTS.with(|inputs| {
+        inputs
+            .borrow_mut()
+            .push(input.start as usize..input.end as usize)
+    });
+    let _forget = Forget;
+    f()
+}
+
+/// Whether `ptr` points into an input [`with_serialized_input`] recorded.
+#[doc(hidden)]
+pub fn is_serialized_input<T>(ptr: *const T) -> bool {
+    SERIALIZED_INPUTS.with(|inputs| {
+        inputs
+            .borrow()
+            .iter()
+            .any(|input| input.contains(&(ptr as usize)))
+    })
+}
+
+/// Unwinds to the innermost [`catch_program_abort`] once a program has
+/// reported a panic through `sol_panic_`, which can't return. Without one, the
+/// process has to end.
+#[doc(hidden)]
+pub fn stop_panicked_program() -> ! {
+    if PROGRAM_ABORT_CATCHERS.with(|catchers| catchers.get()) > 0 {
+        std::panic::resume_unwind(Box::new(ProgramAborted));
+    }
+    std::process::abort()
+}
+
+/// Unwinds to the innermost [`catch_program_abort`] if the loader reports
+/// that it aborted the program. Without one, the program has to run on.
+#[doc(hidden)]
+pub fn stop_if_aborted(stubs_api: &crate::common::abi::SyscallStubsApi) {
+    if PROGRAM_ABORT_CATCHERS.with(|catchers| catchers.get()) > 0
+        && (stubs_api.program_aborted)() != 0
+    {
+        // Not a panic: the hook must not report it to the loader again.
+        std::panic::resume_unwind(Box::new(ProgramAborted));
+    }
+}